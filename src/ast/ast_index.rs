@@ -14,14 +14,14 @@ use crate::ast::fst_extra_automation::Substring;
 use crate::ast::structs::SymbolsSearchResultStruct;
 use crate::ast::treesitter::language_id::LanguageId;
 use crate::ast::treesitter::parsers::get_parser_by_filename;
-use crate::ast::treesitter::structs::{SymbolDeclarationStruct, UsageSymbolInfo};
+use crate::ast::treesitter::structs::{SymbolDeclarationStruct, SymbolInfo, UsageSymbolInfo};
 use crate::files_in_workspace::DocumentInfo;
 
 #[derive(Debug)]
 pub struct AstIndex {
     declarations: HashMap<String, SymbolDeclarationStruct>,
     declarations_search_index: HashMap<PathBuf, Set<Vec<u8>>>,
-    usages: HashMap<String, Vec<Box<dyn UsageSymbolInfo>>>,
+    usages: HashMap<String, Vec<(PathBuf, Box<dyn UsageSymbolInfo>)>>,  // usages don't know their file
     usages_search_index: HashMap<PathBuf, Set<Vec<u8>>>,
 }
 
@@ -65,21 +65,25 @@ impl AstIndex {
 
     pub fn get_declarations_and_usages(doc: &DocumentInfo)
                                        -> Result<(HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>), String> {
-        let path = doc.get_path();
-        let mut parser = match get_parser_by_filename(&doc.get_path()) {
+        let text = match doc.read_file_blocked() {
+            Ok(s) => s,
+            Err(e) => return Err(e.to_string())
+        };
+        AstIndex::parse_declarations_and_usages(&doc.get_path(), &text)
+    }
+
+    pub fn parse_declarations_and_usages(path: &PathBuf, text: &String)
+                                         -> Result<(HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>), String> {
+        let mut parser = match get_parser_by_filename(path) {
             Ok(parser) => parser,
             Err(err) => {
                 return Err(err.message);
             }
         };
-        let text = match doc.read_file_blocked() {
-            Ok(s) => s,
-            Err(e) => return Err(e.to_string())
-        };
 
         // Parse the text and get the declarations and usages
         let t_declarations = std::time::Instant::now();
-        let declarations = match parser.parse_declarations(text.as_str(), &path) {
+        let declarations = match parser.parse_declarations(text.as_str(), path) {
             Ok(declarations) => declarations,
             Err(e) => {
                 return Err(format!("Error parsing {}: {}", path.display(), e));
@@ -132,7 +136,7 @@ impl AstIndex {
         let mut usages_meta_names: SortedVec<String> = SortedVec::new();
         for usage in usages {
             usages_meta_names.push(usage.meta_path());
-            self.usages.entry(usage.meta_path()).or_default().push(usage);
+            self.usages.entry(usage.meta_path()).or_default().push((path.clone(), usage));
         }
         let meta_names_set = match Set::from_iter(usages_meta_names.iter()) {
             Ok(set) => set,
@@ -170,7 +174,13 @@ impl AstIndex {
                         continue;
                     }
                 };
-                self.usages.remove(&name);
+                // other files use the same names
+                if let Some(usages) = self.usages.get_mut(&name) {
+                    usages.retain(|(usage_path, _)| *usage_path != path);
+                    if usages.is_empty() {
+                        self.usages.remove(&name);
+                    }
+                }
             }
         }
         Ok(())
@@ -251,6 +261,7 @@ impl AstIndex {
             .par_iter()
             .filter_map(|k| self.usages.get(k))
             .flatten()
+            .map(|(_, usage)| usage)
             .filter(|k|
                 !k.meta_path().is_empty() && k.get_declaration_meta_path().is_some()
             )
@@ -295,6 +306,16 @@ impl AstIndex {
         Ok(search_results)
     }
 
+    // Where the symbol is used, in all files. symbol_path is what a usage parser gives, "Type::name" for example.
+    pub fn search_usage_locations(&self, symbol_path: &str, top_n: usize) -> Vec<SymbolInfo> {
+        self.usages.get(symbol_path)
+            .into_iter()
+            .flatten()
+            .map(|(path, usage)| SymbolInfo { path: path.clone(), range: usage.get_range() })
+            .take(top_n)
+            .collect()
+    }
+
     pub fn get_symbols_by_file_path(&self, doc: &DocumentInfo) -> Result<Vec<SymbolDeclarationStruct>, String> {
         let path = doc.get_path();
         let mut result: Vec<SymbolDeclarationStruct> = vec![];
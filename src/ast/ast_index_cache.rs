@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use md5;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex as AMutex;
+use tracing::{info, warn};
+
+use crate::ast::ast_index::AstIndex;
+use crate::ast::treesitter::parsers::all_parser_queries;
+use crate::ast::treesitter::structs::{SymbolDeclarationStruct, UsageSymbolInfo};
+use crate::files_in_workspace::DocumentInfo;
+
+const AST_INDEX_CACHE_FILE: &str = "ast_index_cache.json";
+// Bump by hand when parsers extract symbols differently with the same queries, or AstFileCacheEntry changes
+const AST_CACHE_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AstFileCacheEntry {
+    pub mtime: u64,
+    pub content_hash: String,
+    pub declarations: HashMap<String, SymbolDeclarationStruct>,
+    pub usages: Vec<Box<dyn UsageSymbolInfo>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AstIndexCacheOnDisk {
+    version: String,
+    files: HashMap<PathBuf, AstFileCacheEntry>,
+}
+
+// The same as AstIndexCacheOnDisk, borrowed to save the files without copying them
+#[derive(Serialize)]
+struct AstIndexCacheOnDiskRef<'a> {
+    version: &'a String,
+    files: &'a HashMap<PathBuf, AstFileCacheEntry>,
+}
+
+#[derive(Debug)]
+pub struct AstIndexCache {
+    cache_path: PathBuf,
+    version: String,
+    files: HashMap<PathBuf, AstFileCacheEntry>,
+    dirty: bool,
+}
+
+pub type DeclarationsAndUsages = (HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>);
+
+fn str_hash(s: &String) -> String {
+    let digest = md5::compute(s);
+    format!("{:x}", digest)
+}
+
+fn file_mtime(path: &PathBuf) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+// Parsers change between releases and during development, either way cached results are no good.
+// Query changes are noticed by the hash, changes in the code around them need AST_CACHE_FORMAT bumped.
+fn cache_version() -> String {
+    format!("{}-{}-{:x}", env!("CARGO_PKG_VERSION"), AST_CACHE_FORMAT, md5::compute(all_parser_queries()))
+}
+
+impl AstIndexCache {
+    // Blocking, checks every cached file exists: files deleted since the last run are not worth keeping
+    pub fn load(cache_dir: &PathBuf) -> AstIndexCache {
+        let cache_path = cache_dir.join(AST_INDEX_CACHE_FILE);
+        let version = cache_version();
+        let files = match std::fs::read_to_string(&cache_path) {
+            Ok(text) => match serde_json::from_str::<AstIndexCacheOnDisk>(&text) {
+                Ok(on_disk) if on_disk.version == version => {
+                    let mut files = on_disk.files;
+                    files.retain(|path, _| path.exists());
+                    files
+                }
+                Ok(on_disk) => {
+                    info!("AST cache version {} != {}, starting from scratch", on_disk.version, version);
+                    HashMap::new()
+                }
+                Err(e) => {
+                    warn!("cannot parse AST cache {}: {}", cache_path.display(), e);
+                    HashMap::new()
+                }
+            },
+            Err(_) => HashMap::new(),
+        };
+        info!("AST cache loaded {} files from {}", files.len(), cache_path.display());
+        AstIndexCache {
+            cache_path,
+            version,
+            files,
+            dirty: false,
+        }
+    }
+
+    pub fn get(&self, path: &PathBuf) -> Option<AstFileCacheEntry> {
+        self.files.get(path).cloned()
+    }
+
+    pub fn put(&mut self, path: PathBuf, entry: AstFileCacheEntry) {
+        self.files.insert(path, entry);
+        self.dirty = true;
+    }
+
+}
+
+// The lock is held only to serialize, the indexer can go on while the file is written
+pub async fn ast_index_cache_save(ast_index_cache: Arc<AMutex<AstIndexCache>>) -> Result<(), String> {
+    let (cache_path, text, files_count) = {
+        let mut cache_locked = ast_index_cache.lock().await;
+        if !cache_locked.dirty {
+            return Ok(());
+        }
+        let on_disk = AstIndexCacheOnDiskRef {
+            version: &cache_locked.version,
+            files: &cache_locked.files,
+        };
+        let text = serde_json::to_string(&on_disk).map_err(|e| e.to_string())?;
+        cache_locked.dirty = false;
+        (cache_locked.cache_path.clone(), text, cache_locked.files.len())
+    };
+    let written = async {
+        let tmp_path = cache_path.with_extension("json.tmp");
+        if let Some(parent) = cache_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&tmp_path, text).await?;
+        tokio::fs::rename(&tmp_path, &cache_path).await
+    }.await;
+    if let Err(e) = written {
+        ast_index_cache.lock().await.dirty = true;
+        return Err(e.to_string());
+    }
+    info!("AST cache saved {} files to {}", files_count, cache_path.display());
+    Ok(())
+}
+
+// Returns declarations and usages, plus a new cache entry if the old one was missing or outdated.
+pub fn get_declarations_and_usages_cached(
+    doc: &DocumentInfo,
+    cached: Option<AstFileCacheEntry>,
+) -> Result<(DeclarationsAndUsages, Option<AstFileCacheEntry>), String> {
+    let path = doc.get_path();
+    // unsaved text from the IDE has no meaningful mtime, check the hash only
+    let mtime = match doc.document {
+        Some(_) => None,
+        None => file_mtime(&path),
+    };
+    let mtime_matches = matches!((cached.as_ref(), mtime), (Some(entry), Some(mtime)) if entry.mtime == mtime);
+    if mtime_matches {
+        let entry = cached.unwrap();
+        return Ok(((entry.declarations, entry.usages), None));
+    }
+
+    let text = doc.read_file_blocked().map_err(|e| e.to_string())?;
+    let content_hash = str_hash(&text);
+    if let Some(entry) = cached {
+        if entry.content_hash == content_hash {
+            let updated = AstFileCacheEntry {
+                mtime: mtime.unwrap_or(0),
+                ..entry
+            };
+            return Ok(((updated.declarations.clone(), updated.usages.clone()), Some(updated)));
+        }
+    }
+
+    let (declarations, usages) = AstIndex::parse_declarations_and_usages(&path, &text)?;
+    let entry = AstFileCacheEntry {
+        mtime: mtime.unwrap_or(0),
+        content_hash,
+        declarations: declarations.clone(),
+        usages: usages.clone(),
+    };
+    Ok(((declarations, usages), Some(entry)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_hit_skips_parsing() {
+        let mut file = tempfile::Builder::new().suffix(".py").tempfile().unwrap();
+        write!(file, "def foo():\n    return 1\n").unwrap();
+        let doc = DocumentInfo::from_pathbuf(&file.path().to_path_buf()).unwrap();
+
+        let ((declarations, _), entry) = get_declarations_and_usages_cached(&doc, None).unwrap();
+        let entry = entry.expect("first parse should produce a cache entry");
+        assert_eq!(declarations.len(), 1);
+
+        let ((cached_declarations, _), new_entry) = get_declarations_and_usages_cached(&doc, Some(entry.clone())).unwrap();
+        assert!(new_entry.is_none());
+        assert_eq!(cached_declarations, declarations);
+
+        // same content, different mtime: entry is refreshed but not re-parsed
+        let touched = AstFileCacheEntry { mtime: 0, ..entry.clone() };
+        let (_, new_entry) = get_declarations_and_usages_cached(&doc, Some(touched)).unwrap();
+        assert_eq!(new_entry.unwrap().declarations, entry.declarations);
+
+        // changed content is re-parsed
+        write!(file, "def bar():\n    return 2\n").unwrap();
+        let stale = AstFileCacheEntry { mtime: 0, ..entry };
+        let ((declarations, _), new_entry) = get_declarations_and_usages_cached(&doc, Some(stale)).unwrap();
+        assert_eq!(declarations.len(), 2);
+        assert!(new_entry.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_drops_deleted_files() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut file = tempfile::Builder::new().suffix(".py").tempfile().unwrap();
+        write!(file, "def foo():\n    return 1\n").unwrap();
+        let path = file.path().to_path_buf();
+        let doc = DocumentInfo::from_pathbuf(&path).unwrap();
+        let (_, entry) = get_declarations_and_usages_cached(&doc, None).unwrap();
+        let entry = entry.unwrap();
+
+        let cache = Arc::new(AMutex::new(AstIndexCache::load(&cache_dir.path().to_path_buf())));
+        cache.lock().await.put(path.clone(), entry.clone());
+        cache.lock().await.put(cache_dir.path().join("deleted.py"), entry.clone());
+        ast_index_cache_save(cache.clone()).await.unwrap();
+        assert!(!cache.lock().await.dirty);
+
+        let loaded = AstIndexCache::load(&cache_dir.path().to_path_buf());
+        assert_eq!(loaded.files.len(), 1);
+        assert_eq!(loaded.get(&path).unwrap().declarations, entry.declarations);
+    }
+}
@@ -10,7 +10,7 @@ use tokio::task::JoinHandle;
 use tracing::info;
 use rayon::prelude::*;
 use crate::ast::ast_index::AstIndex;
-use crate::ast::treesitter::structs::{SymbolDeclarationStruct, UsageSymbolInfo};
+use crate::ast::ast_index_cache::{AstFileCacheEntry, AstIndexCache, DeclarationsAndUsages, ast_index_cache_save, get_declarations_and_usages_cached};
 use crate::files_in_workspace::DocumentInfo;
 
 #[derive(Debug)]
@@ -18,6 +18,7 @@ pub struct AstIndexService {
     update_request_queue: Arc<AMutex<VecDeque<DocumentInfo>>>,
     output_queue: Arc<AMutex<VecDeque<DocumentInfo>>>,
     ast_index: Arc<AMutex<AstIndex>>,
+    ast_index_cache: Arc<AMutex<AstIndexCache>>,
 }
 
 async fn cooldown_queue_thread(
@@ -68,6 +69,7 @@ async fn cooldown_queue_thread(
 async fn ast_indexer_thread(
     queue: Arc<AMutex<VecDeque<DocumentInfo>>>,
     ast_index: Arc<AMutex<AstIndex>>,
+    ast_index_cache: Arc<AMutex<AstIndexCache>>,
 ) {
     let mut reported_unprocessed: usize = 0;
     let mut reported_astindex_complete: bool = false;
@@ -91,22 +93,32 @@ async fn ast_indexer_thread(
                 reported_astindex_complete = true;
                 write!(std::io::stderr(), "AST COMPLETED\n").unwrap();
                 info!("AST COMPLETED");
+                if let Err(e) = ast_index_cache_save(ast_index_cache.clone()).await {
+                    info!("Error saving AST cache: {}", e);
+                }
             }
             tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
             continue;
         }
 
 
-        let ast_index = ast_index.clone();
-        let declarations_and_usages: Vec<Result<(HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>), String>>
-            = list_of_path.par_iter().map(move |document| {
-            AstIndex::get_declarations_and_usages(&document)
+        let cached_entries: Vec<Option<AstFileCacheEntry>> = {
+            let cache_locked = ast_index_cache.lock().await;
+            list_of_path.iter().map(|doc| cache_locked.get(&doc.get_path())).collect()
+        };
+        let declarations_and_usages: Vec<Result<(DeclarationsAndUsages, Option<AstFileCacheEntry>), String>>
+            = list_of_path.par_iter().zip(cached_entries).map(|(document, cached)| {
+            get_declarations_and_usages_cached(&document, cached)
         }).collect();
 
+        let mut cache_locked = ast_index_cache.lock().await;
         let mut ast_index = ast_index.lock().await;
         zip(list_of_path, declarations_and_usages).for_each(|(doc, res)| {
             match res {
-                Ok((declaration, usages)) => {
+                Ok(((declaration, usages), new_cache_entry)) => {
+                    if let Some(entry) = new_cache_entry {
+                        cache_locked.put(doc.get_path(), entry);
+                    }
                     match ast_index.add_or_update_declarations_and_usages(&doc, declaration, usages) {
                         Ok(_) => {}
                         Err(e) => { info!("Error adding/updating records in AST index: {}", e);}
@@ -122,7 +134,8 @@ const COOLDOWN_SECS: u64 = 5;
 
 impl AstIndexService {
     pub fn init(
-        ast_index: Arc<AMutex<AstIndex>>
+        ast_index: Arc<AMutex<AstIndex>>,
+        ast_index_cache: Arc<AMutex<AstIndexCache>>,
     ) -> Self {
         let update_request_queue = Arc::new(AMutex::new(VecDeque::new()));
         let output_queue = Arc::new(AMutex::new(VecDeque::new()));
@@ -130,6 +143,7 @@ impl AstIndexService {
             update_request_queue: update_request_queue.clone(),
             output_queue: output_queue.clone(),
             ast_index: ast_index.clone(),
+            ast_index_cache: ast_index_cache.clone(),
         }
     }
 
@@ -145,6 +159,7 @@ impl AstIndexService {
             ast_indexer_thread(
                 self.output_queue.clone(),
                 self.ast_index.clone(),
+                self.ast_index_cache.clone(),
             )
         );
         return vec![cooldown_queue_join_handle, indexer_handle];
@@ -11,10 +11,12 @@ use tree_sitter::Point;
 
 use crate::global_context::GlobalContext;
 use crate::ast::ast_index::AstIndex;
+use crate::ast::ast_index_cache::AstIndexCache;
 use crate::ast::ast_index_service::AstIndexService;
 use crate::ast::comments_wrapper::get_language_id_by_filename;
 use crate::ast::structs::{AstCursorSearchResult, AstQuerySearchResult, CursorUsagesResult, FileReferencesResult, SymbolsSearchResultStruct, UsageSearchResultStruct};
 use crate::ast::treesitter::parsers::get_parser_by_filename;
+use crate::ast::treesitter::structs::SymbolInfo;
 use crate::files_in_workspace::DocumentInfo;
 use rayon::prelude::*;
 use crate::files_in_jsonl::files_in_jsonl;
@@ -35,8 +37,12 @@ impl AstModule {
     pub async fn ast_indexer_init(
         global_context: Arc<ARwLock<GlobalContext>>,
     ) -> Result<AstModule, String> {
+        let cache_dir = global_context.read().await.cache_dir.clone();
         let ast_index = Arc::new(AMutex::new(AstIndex::init()));
-        let ast_index_service = Arc::new(AMutex::new(AstIndexService::init(ast_index.clone())));
+        let ast_index_cache = tokio::task::spawn_blocking(move || AstIndexCache::load(&cache_dir)).await
+            .map_err(|e| format!("AST cache load: {}", e))?;
+        let ast_index_cache = Arc::new(AMutex::new(ast_index_cache));
+        let ast_index_service = Arc::new(AMutex::new(AstIndexService::init(ast_index.clone(), ast_index_cache)));
 
         let documents = files_in_jsonl(global_context.clone()).await;
         let me = AstModule {
@@ -240,6 +246,12 @@ impl AstModule {
         }
     }
 
+    pub async fn search_usage_locations(&self, symbol_path: &str, top_n: usize) -> Vec<SymbolInfo> {
+        let ast_index = self.ast_index.clone();
+        let ast_index_locked = ast_index.lock().await;
+        ast_index_locked.search_usage_locations(symbol_path, top_n)
+    }
+
     pub async fn get_file_symbols(&self, doc: &DocumentInfo) -> Result<FileReferencesResult, String> {
         let ast_index = self.ast_index.clone();
         let ast_index_locked = ast_index.lock().await;
@@ -13,6 +13,9 @@ pub fn get_language_id_by_filename(filename: &PathBuf) -> Option<LanguageId> {
         "rs" => Some(LanguageId::Rust),
         "ts" => Some(LanguageId::TypeScript),
         "tsx" => Some(LanguageId::TypeScriptReact),
+        "go" => Some(LanguageId::Go),
+        "cs" => Some(LanguageId::CSharp),
+        "kt" | "kts" => Some(LanguageId::Kotlin),
         _ => None
     }
 }
@@ -1,5 +1,6 @@
-mod treesitter;
+pub(crate) mod treesitter;
 pub mod ast_index;
+pub mod ast_index_cache;
 pub mod ast_index_service;
 pub mod ast_module;
 pub mod structs;
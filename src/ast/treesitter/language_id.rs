@@ -155,6 +155,12 @@ impl From<Language> for LanguageId {
             Self::TypeScript
         } else if value == tree_sitter_typescript::language_tsx() {
             Self::TypeScriptReact
+        } else if value == tree_sitter_go::language() {
+            Self::Go
+        } else if value == tree_sitter_c_sharp::language() {
+            Self::CSharp
+        } else if value == tree_sitter_kotlin::language() {
+            Self::Kotlin
         } else {
             Self::Unknown
         }
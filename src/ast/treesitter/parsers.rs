@@ -17,6 +17,9 @@ pub(crate) mod rust;
 pub(crate) mod js;
 pub(crate) mod ts;
 pub(crate) mod tsx;
+pub(crate) mod go;
+pub(crate) mod csharp;
+pub(crate) mod kotlin;
 #[cfg(test)]
 mod tests;
 mod utils;
@@ -150,6 +153,26 @@ pub trait LanguageParser: Send {
                                            extra_declarations: vec![],
                                        });
                     }
+                    "field" => {
+                        let range = capture.node.range();
+                        let mut namespaces = self.get_namespace(Some(capture.node), code);
+                        let name = self.get_variable_name(capture.node, code);
+                        let mut key = path.to_str().unwrap().to_string();
+                        namespaces.push(name.clone());
+                        namespaces.iter().for_each(|ns| {
+                            key += format!("::{}", ns).as_str();
+                        });
+                        indexes.insert(key.clone(),
+                                       SymbolDeclarationStruct {
+                                           name,
+                                           definition_info: SymbolInfo { path: path.clone(), range },
+                                           children: vec![],
+                                           symbol_type: SymbolType::Field,
+                                           meta_path: key,
+                                           language: LanguageId::from(capture.node.language()),
+                                           extra_declarations: vec![],
+                                       });
+                    }
                     &_ => {}
                 }
             }
@@ -230,12 +253,35 @@ fn get_parser(language_id: LanguageId) -> Result<Box<dyn LanguageParser + 'stati
             let parser = tsx::TypescriptxParser::new()?;
             Ok(Box::new(parser))
         }
+        LanguageId::Go => {
+            let parser = go::GoParser::new()?;
+            Ok(Box::new(parser))
+        }
+        LanguageId::CSharp => {
+            let parser = csharp::CSharpParser::new()?;
+            Ok(Box::new(parser))
+        }
+        LanguageId::Kotlin => {
+            let parser = kotlin::KotlinParser::new()?;
+            Ok(Box::new(parser))
+        }
         other => Err(ParserError {
             message: "Unsupported language id: ".to_string() + &other.to_string()
         }),
     }
 }
 
+// A change in any of them makes parse results cached on disk outdated
+pub(crate) fn all_parser_queries() -> String {
+    [LanguageId::Cpp, LanguageId::Python, LanguageId::Java, LanguageId::JavaScript, LanguageId::Rust,
+        LanguageId::TypeScript, LanguageId::TypeScriptReact, LanguageId::Go, LanguageId::CSharp, LanguageId::Kotlin]
+        .into_iter()
+        .filter_map(|language_id| get_parser(language_id).ok())
+        .map(|parser| format!("{}\n{}", parser.get_parser_query(), parser.get_parser_query_find_all()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub fn get_parser_by_filename(filename: &PathBuf) -> Result<Box<dyn LanguageParser + 'static>, ParserError> {
     let suffix = filename.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
     let maybe_language_id = get_language_id_by_filename(filename);
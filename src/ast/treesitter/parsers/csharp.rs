@@ -0,0 +1,218 @@
+use std::string::ToString;
+
+use similar::DiffableStr;
+use structopt::lazy_static::lazy_static;
+use tree_sitter::{Node, Parser, Query, QueryCapture, Range};
+use tree_sitter_c_sharp::language;
+
+use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
+use crate::ast::treesitter::parsers::utils::get_function_name;
+use crate::ast::treesitter::structs::VariableInfo;
+
+const CSHARP_PARSER_QUERY_GLOBAL_VARIABLE: &str = "";
+const CSHARP_PARSER_QUERY_FUNCTION: &str = "((method_declaration name: (identifier)) @function)\n\
+((constructor_declaration name: (identifier)) @function)";
+const CSHARP_PARSER_QUERY_CLASS: &str = "((class_declaration name: (identifier)) @class)\n\
+((struct_declaration name: (identifier)) @struct)\n\
+((interface_declaration name: (identifier)) @trait)\n\
+((enum_declaration name: (identifier)) @enum)";
+const CSHARP_PARSER_QUERY_FIELD: &str = "(property_declaration name: (identifier) @field)\n\
+(field_declaration (variable_declaration (variable_declarator name: (identifier) @field)))";
+const CSHARP_PARSER_QUERY_CALL_FUNCTION: &str = "";
+const CSHARP_PARSER_QUERY_IMPORT_STATEMENT: &str = "";
+const CSHARP_PARSER_QUERY_IMPORT_FROM_STATEMENT: &str = "";
+const CSHARP_PARSER_QUERY_CLASS_METHOD: &str = "";
+
+const CSHARP_PARSER_QUERY_FIND_VARIABLES: &str = r#"
+((local_declaration_statement (variable_declaration
+type: (_) @variable_type
+(variable_declarator name: (identifier) @variable_name))) @variable)"#;
+
+const CSHARP_PARSER_QUERY_FIND_CALLS: &str = r#"
+((invocation_expression function: [
+(identifier) @call_name
+(member_access_expression name: (identifier) @call_name)
+]) @call)"#;
+
+const CSHARP_PARSER_QUERY_FIND_STATICS: &str = r#"(
+([
+(comment) @comment
+(string_literal) @string_literal
+(verbatim_string_literal) @string_literal
+])
+)"#;
+
+const TRY_TO_FIND_TYPE_QUERY: &str = "(object_creation_expression type: (_) @variable_type)";
+
+lazy_static! {
+    static ref CSHARP_PARSER_QUERY: String = {
+        let mut m = Vec::new();
+        m.push(CSHARP_PARSER_QUERY_GLOBAL_VARIABLE);
+        m.push(CSHARP_PARSER_QUERY_FUNCTION);
+        m.push(CSHARP_PARSER_QUERY_CLASS);
+        m.push(CSHARP_PARSER_QUERY_FIELD);
+        m.push(CSHARP_PARSER_QUERY_CALL_FUNCTION);
+        m.push(CSHARP_PARSER_QUERY_IMPORT_STATEMENT);
+        m.push(CSHARP_PARSER_QUERY_IMPORT_FROM_STATEMENT);
+        m.push(CSHARP_PARSER_QUERY_CLASS_METHOD);
+        m.join("\n")
+    };
+
+    static ref CSHARP_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}",
+        CSHARP_PARSER_QUERY_FIND_VARIABLES, CSHARP_PARSER_QUERY_FIND_CALLS, CSHARP_PARSER_QUERY_FIND_STATICS);
+
+    static ref NAME_ID: u16 = language().field_id_for_name("name").unwrap();
+}
+
+pub(crate) struct CSharpParser {
+    pub parser: Parser,
+}
+
+impl CSharpParser {
+    pub fn new() -> Result<CSharpParser, ParserError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(language())
+            .map_err(internal_error)?;
+        Ok(CSharpParser { parser })
+    }
+}
+
+fn try_to_find_type(parser: &mut Parser, parent: &Node, code: &str) -> Option<String> {
+    let mut qcursor = tree_sitter::QueryCursor::new();
+    let query = Query::new(parser.language().unwrap(), TRY_TO_FIND_TYPE_QUERY).unwrap();
+    let matches = qcursor.matches(&query, *parent, code.as_bytes());
+    for match_ in matches {
+        for capture in match_.captures {
+            return Some(code.slice(capture.node.byte_range()).to_string());
+        }
+    }
+    None
+}
+
+impl LanguageParser for CSharpParser {
+    fn get_parser(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    fn get_parser_query(&self) -> &String {
+        &CSHARP_PARSER_QUERY
+    }
+
+    fn get_parser_query_find_all(&self) -> &String {
+        &CSHARP_PARSER_QUERY_FIND_ALL
+    }
+
+    fn get_namespace(&self, mut parent: Option<Node>, text: &str) -> Vec<String> {
+        let mut namespaces: Vec<String> = vec![];
+        while parent.is_some() {
+            match parent.unwrap().kind() {
+                "namespace_declaration" | "file_scoped_namespace_declaration" |
+                "class_declaration" | "struct_declaration" | "interface_declaration" => {
+                    if let Some(child) = parent.unwrap().child_by_field_id(*NAME_ID) {
+                        namespaces.push(text.slice(child.byte_range()).to_string());
+                    }
+                }
+                _ => {}
+            }
+            parent = parent.unwrap().parent();
+        }
+        namespaces.reverse();
+        namespaces
+    }
+
+    fn get_enum_name_and_all_values(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        let mut name: String = Default::default();
+        let mut values: Vec<String> = vec![];
+        if let Some(child) = parent.child_by_field_id(*NAME_ID) {
+            name = text.slice(child.byte_range()).to_string();
+        }
+        for i in 0..parent.child_count() {
+            if let Some(child) = parent.child(i) {
+                if child.kind() != "enum_member_declaration_list" {
+                    continue;
+                }
+                for i in 0..child.child_count() {
+                    if let Some(member) = child.child(i) {
+                        if member.kind() != "enum_member_declaration" {
+                            continue;
+                        }
+                        if let Some(member_name) = member.child_by_field_id(*NAME_ID) {
+                            values.push(text.slice(member_name.byte_range()).to_string());
+                        }
+                    }
+                }
+            }
+        }
+        (name, values)
+    }
+
+    fn get_variable(&mut self, captures: &[QueryCapture], query: &Query, code: &str) -> Option<VariableInfo> {
+        let mut var = VariableInfo {
+            name: "".to_string(),
+            range: Range {
+                start_byte: 0,
+                end_byte: 0,
+                start_point: Default::default(),
+                end_point: Default::default(),
+            },
+            type_names: vec![],
+            meta_path: None,
+        };
+        for capture in captures {
+            let capture_name = &query.capture_names()[capture.index as usize];
+            match capture_name.as_str() {
+                "variable" => {
+                    var.range = capture.node.range();
+                }
+                "variable_name" => {
+                    let text = code.slice(capture.node.byte_range());
+                    var.name = text.to_string();
+                }
+                "variable_type" => {
+                    // `var x = new Foo()` -> take the type from the initializer
+                    if capture.node.kind() == "implicit_type" {
+                        if let Some(parent) = capture.node.parent() {
+                            if let Some(var_type) = try_to_find_type(&mut self.parser, &parent, code) {
+                                var.type_names.push(var_type);
+                            }
+                        }
+                    } else {
+                        let text = code.slice(capture.node.byte_range());
+                        var.type_names.push(text.to_string());
+                    }
+                }
+                &_ => {}
+            }
+        }
+
+        if var.name.is_empty() {
+            return None;
+        }
+
+        Some(var)
+    }
+
+    fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        (get_function_name(parent, text), vec![])
+    }
+
+    fn get_variable_name(&self, parent: Node, text: &str) -> String {
+        if parent.kind() == "identifier" {
+            return text.slice(parent.byte_range()).to_string();
+        }
+        for i in 0..parent.child_count() {
+            if let Some(child) = parent.child(i) {
+                let kind = child.kind();
+                match kind {
+                    "identifier" => {
+                        let name = text.slice(child.byte_range());
+                        return name.to_string();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        return "".to_string();
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+use std::string::ToString;
+
+use similar::DiffableStr;
+use structopt::lazy_static::lazy_static;
+use tree_sitter::{Node, Parser, Query, QueryCapture, Range, Tree};
+use tree_sitter_go::language;
+
+use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
+use crate::ast::treesitter::parsers::utils::get_function_name;
+use crate::ast::treesitter::structs::{SymbolInfo, VariableInfo};
+
+const GO_PARSER_QUERY_GLOBAL_VARIABLE: &str = "(source_file (var_declaration (var_spec name: (identifier) @global_variable)))\n\
+(source_file (const_declaration (const_spec name: (identifier) @global_variable)))";
+const GO_PARSER_QUERY_FUNCTION: &str = "((function_declaration name: (identifier)) @function)\n\
+((method_declaration name: (field_identifier)) @function)\n\
+((method_spec name: (field_identifier)) @function)";
+const GO_PARSER_QUERY_CLASS: &str = "((type_spec name: (type_identifier) type: (struct_type)) @struct)\n\
+((type_spec name: (type_identifier) type: (interface_type)) @trait)";
+const GO_PARSER_QUERY_FIELD: &str = "(field_declaration name: (field_identifier) @field)";
+const GO_PARSER_QUERY_CALL_FUNCTION: &str = "";
+const GO_PARSER_QUERY_IMPORT_STATEMENT: &str = "";
+const GO_PARSER_QUERY_IMPORT_FROM_STATEMENT: &str = "";
+const GO_PARSER_QUERY_CLASS_METHOD: &str = "";
+
+const GO_PARSER_QUERY_FIND_VARIABLES: &str = r#"([
+(short_var_declaration left: (expression_list (identifier) @variable_name))
+(var_spec name: (identifier) @variable_name)
+] @variable)"#;
+
+const GO_PARSER_QUERY_FIND_CALLS: &str = r#"
+((call_expression function: [
+(identifier) @call_name
+(selector_expression field: (field_identifier) @call_name)
+]) @call)"#;
+
+const GO_PARSER_QUERY_FIND_STATICS: &str = r#"(
+([
+(comment) @comment
+(interpreted_string_literal) @string_literal
+(raw_string_literal) @string_literal
+])
+)"#;
+
+const TRY_TO_FIND_TYPE_QUERY: &str = "[
+    (var_spec type: (_) @variable_type)
+    (composite_literal type: (type_identifier) @variable_type)
+    (composite_literal type: (qualified_type) @variable_type)
+    ]";
+
+lazy_static! {
+    static ref GO_PARSER_QUERY: String = {
+        let mut m = Vec::new();
+        m.push(GO_PARSER_QUERY_GLOBAL_VARIABLE);
+        m.push(GO_PARSER_QUERY_FUNCTION);
+        m.push(GO_PARSER_QUERY_CLASS);
+        m.push(GO_PARSER_QUERY_FIELD);
+        m.push(GO_PARSER_QUERY_CALL_FUNCTION);
+        m.push(GO_PARSER_QUERY_IMPORT_STATEMENT);
+        m.push(GO_PARSER_QUERY_IMPORT_FROM_STATEMENT);
+        m.push(GO_PARSER_QUERY_CLASS_METHOD);
+        m.join("\n")
+    };
+
+    static ref GO_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}",
+        GO_PARSER_QUERY_FIND_VARIABLES, GO_PARSER_QUERY_FIND_CALLS, GO_PARSER_QUERY_FIND_STATICS);
+
+    static ref NAME_ID: u16 = language().field_id_for_name("name").unwrap();
+    static ref RECEIVER_ID: u16 = language().field_id_for_name("receiver").unwrap();
+    static ref TYPE_ID: u16 = language().field_id_for_name("type").unwrap();
+}
+
+pub(crate) struct GoParser {
+    pub parser: Parser,
+}
+
+impl GoParser {
+    pub fn new() -> Result<GoParser, ParserError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(language())
+            .map_err(internal_error)?;
+        Ok(GoParser { parser })
+    }
+}
+
+fn try_to_find_type(parser: &mut Parser, parent: &Node, code: &str) -> Option<String> {
+    let mut qcursor = tree_sitter::QueryCursor::new();
+    let query = Query::new(parser.language().unwrap(), TRY_TO_FIND_TYPE_QUERY).unwrap();
+    let matches = qcursor.matches(&query, *parent, code.as_bytes());
+    for match_ in matches {
+        for capture in match_.captures {
+            return Some(code.slice(capture.node.byte_range()).to_string());
+        }
+    }
+    None
+}
+
+// `func (p *Point) Foo()`, `func (s Stack[T]) Foo()` -> "Point", "Stack"
+fn get_receiver_type_name(type_node: Node, text: &str) -> Option<String> {
+    match type_node.kind() {
+        "type_identifier" => Some(text.slice(type_node.byte_range()).to_string()),
+        "pointer_type" | "generic_type" => {
+            for i in 0..type_node.child_count() {
+                if let Some(child) = type_node.child(i) {
+                    if let Some(name) = get_receiver_type_name(child, text) {
+                        return Some(name);
+                    }
+                }
+            }
+            None
+        }
+        _ => None
+    }
+}
+
+impl LanguageParser for GoParser {
+    fn get_parser(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    fn get_parser_query(&self) -> &String {
+        &GO_PARSER_QUERY
+    }
+
+    fn get_parser_query_find_all(&self) -> &String {
+        &GO_PARSER_QUERY_FIND_ALL
+    }
+
+    fn get_namespace(&self, mut parent: Option<Node>, text: &str) -> Vec<String> {
+        let mut namespaces: Vec<String> = vec![];
+        while parent.is_some() {
+            match parent.unwrap().kind() {
+                "type_spec" => {
+                    if let Some(child) = parent.unwrap().child_by_field_id(*NAME_ID) {
+                        namespaces.push(text.slice(child.byte_range()).to_string());
+                    }
+                }
+                _ => {}
+            }
+            parent = parent.unwrap().parent();
+        }
+        namespaces.reverse();
+        namespaces
+    }
+
+    fn get_extra_declarations_for_struct(&mut self, struct_name: String, tree: &Tree, code: &str, path: &PathBuf) -> Vec<SymbolInfo> {
+        let mut res: Vec<SymbolInfo> = vec![];
+        let mut qcursor = tree_sitter::QueryCursor::new();
+        let query = Query::new(self.get_parser().language().unwrap(),
+                               &*format!("((method_declaration receiver: (parameter_list (parameter_declaration type: [\
+                               (type_identifier) @receiver_type \
+                               (pointer_type (type_identifier) @receiver_type)]))) @method \
+                               (#eq? @receiver_type \"{}\"))", struct_name)).unwrap();
+        let matches = qcursor.matches(&query, tree.root_node(), code.as_bytes());
+        for match_ in matches {
+            for capture in match_.captures {
+                let capture_name = &query.capture_names()[capture.index as usize];
+                match capture_name.as_str() {
+                    "method" => {
+                        res.push(SymbolInfo {
+                            path: path.clone(),
+                            range: capture.node.range(),
+                        })
+                    }
+                    &_ => {}
+                }
+            }
+        }
+        res
+    }
+
+    fn get_variable(&mut self, captures: &[QueryCapture], query: &Query, code: &str) -> Option<VariableInfo> {
+        let mut var = VariableInfo {
+            name: "".to_string(),
+            range: Range {
+                start_byte: 0,
+                end_byte: 0,
+                start_point: Default::default(),
+                end_point: Default::default(),
+            },
+            type_names: vec![],
+            meta_path: None,
+        };
+        for capture in captures {
+            let capture_name = &query.capture_names()[capture.index as usize];
+            match capture_name.as_str() {
+                "variable" => {
+                    var.range = capture.node.range();
+                    if let Some(var_type) = try_to_find_type(&mut self.parser, &capture.node, code) {
+                        var.type_names.push(var_type);
+                    }
+                }
+                "variable_name" => {
+                    let text = code.slice(capture.node.byte_range());
+                    var.name = text.to_string();
+                }
+                &_ => {}
+            }
+        }
+
+        if var.name.is_empty() {
+            return None;
+        }
+
+        Some(var)
+    }
+
+    fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        let mut scope: Vec<String> = vec![];
+        if let Some(receiver) = parent.child_by_field_id(*RECEIVER_ID) {
+            for i in 0..receiver.child_count() {
+                if let Some(child) = receiver.child(i) {
+                    if child.kind() != "parameter_declaration" {
+                        continue;
+                    }
+                    if let Some(type_node) = child.child_by_field_id(*TYPE_ID) {
+                        if let Some(name) = get_receiver_type_name(type_node, text) {
+                            scope.push(name);
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+        (get_function_name(parent, text), scope)
+    }
+
+    fn get_variable_name(&self, parent: Node, text: &str) -> String {
+        match parent.kind() {
+            "identifier" | "field_identifier" => {
+                return text.slice(parent.byte_range()).to_string();
+            }
+            _ => {}
+        }
+        for i in 0..parent.child_count() {
+            if let Some(child) = parent.child(i) {
+                let kind = child.kind();
+                match kind {
+                    "identifier" | "field_identifier" => {
+                        let name = text.slice(child.byte_range());
+                        return name.to_string();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        return "".to_string();
+    }
+}
@@ -0,0 +1,254 @@
+use std::path::PathBuf;
+use std::string::ToString;
+
+use similar::DiffableStr;
+use structopt::lazy_static::lazy_static;
+use tree_sitter::{Node, Parser, Query, QueryCapture, Range, Tree};
+use tree_sitter_kotlin::language;
+
+use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
+use crate::ast::treesitter::structs::{SymbolInfo, VariableInfo};
+
+const KOTLIN_PARSER_QUERY_GLOBAL_VARIABLE: &str = "(source_file (property_declaration) @global_variable)";
+const KOTLIN_PARSER_QUERY_FUNCTION: &str = "((function_declaration) @function)";
+const KOTLIN_PARSER_QUERY_CLASS: &str = "((class_declaration) @class)\n\
+((object_declaration) @class)\n\
+((companion_object) @class)";
+const KOTLIN_PARSER_QUERY_FIELD: &str = "(class_body (property_declaration) @field)";
+const KOTLIN_PARSER_QUERY_CALL_FUNCTION: &str = "";
+const KOTLIN_PARSER_QUERY_IMPORT_STATEMENT: &str = "";
+const KOTLIN_PARSER_QUERY_IMPORT_FROM_STATEMENT: &str = "";
+const KOTLIN_PARSER_QUERY_CLASS_METHOD: &str = "";
+
+const KOTLIN_PARSER_QUERY_FIND_VARIABLES: &str = r#"
+((property_declaration (variable_declaration (simple_identifier) @variable_name)) @variable)"#;
+
+const KOTLIN_PARSER_QUERY_FIND_CALLS: &str = r#"
+((call_expression [
+(simple_identifier) @call_name
+(navigation_expression (navigation_suffix (simple_identifier) @call_name))
+]) @call)"#;
+
+const KOTLIN_PARSER_QUERY_FIND_STATICS: &str = r#"(
+([
+(line_comment) @comment
+(multiline_comment) @comment
+(string_literal) @string_literal
+])
+)"#;
+
+const TRY_TO_FIND_TYPE_QUERY: &str = "[
+    (variable_declaration (user_type) @variable_type)
+    (variable_declaration (nullable_type) @variable_type)
+    (call_expression (simple_identifier) @constructor_call)
+    ]";
+
+lazy_static! {
+    static ref KOTLIN_PARSER_QUERY: String = {
+        let mut m = Vec::new();
+        m.push(KOTLIN_PARSER_QUERY_GLOBAL_VARIABLE);
+        m.push(KOTLIN_PARSER_QUERY_FUNCTION);
+        m.push(KOTLIN_PARSER_QUERY_CLASS);
+        m.push(KOTLIN_PARSER_QUERY_FIELD);
+        m.push(KOTLIN_PARSER_QUERY_CALL_FUNCTION);
+        m.push(KOTLIN_PARSER_QUERY_IMPORT_STATEMENT);
+        m.push(KOTLIN_PARSER_QUERY_IMPORT_FROM_STATEMENT);
+        m.push(KOTLIN_PARSER_QUERY_CLASS_METHOD);
+        m.join("\n")
+    };
+
+    static ref KOTLIN_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}",
+        KOTLIN_PARSER_QUERY_FIND_VARIABLES, KOTLIN_PARSER_QUERY_FIND_CALLS, KOTLIN_PARSER_QUERY_FIND_STATICS);
+}
+
+pub(crate) struct KotlinParser {
+    pub parser: Parser,
+}
+
+impl KotlinParser {
+    pub fn new() -> Result<KotlinParser, ParserError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(language())
+            .map_err(internal_error)?;
+        Ok(KotlinParser { parser })
+    }
+}
+
+fn try_to_find_type(parser: &mut Parser, parent: &Node, code: &str) -> Option<String> {
+    let mut qcursor = tree_sitter::QueryCursor::new();
+    let query = Query::new(parser.language().unwrap(), TRY_TO_FIND_TYPE_QUERY).unwrap();
+    let matches = qcursor.matches(&query, *parent, code.as_bytes());
+    for match_ in matches {
+        for capture in match_.captures {
+            let text = code.slice(capture.node.byte_range());
+            let capture_name = &query.capture_names()[capture.index as usize];
+            // `val x = Foo()` -> there is no `new` in kotlin, so only calls that look like constructors
+            if capture_name == "constructor_call" && !text.starts_with(|c: char| c.is_uppercase()) {
+                continue;
+            }
+            return Some(text.to_string());
+        }
+    }
+    None
+}
+
+fn get_child_text_by_kind(parent: Node, kind: &str, text: &str) -> Option<String> {
+    for i in 0..parent.child_count() {
+        if let Some(child) = parent.child(i) {
+            if child.kind() == kind {
+                return Some(text.slice(child.byte_range()).to_string());
+            }
+        }
+    }
+    None
+}
+
+// `fun Foo.bar()`, `fun Foo?.bar()`, `fun List<T>.bar()` -> "Foo", "Foo", "List"
+fn get_receiver_type_name(function: Node, text: &str) -> Option<String> {
+    for i in 0..function.child_count() {
+        if let Some(child) = function.child(i) {
+            match child.kind() {
+                "simple_identifier" => return None,
+                "user_type" => return get_user_type_name(child, text),
+                "nullable_type" => {
+                    for j in 0..child.child_count() {
+                        if let Some(inner) = child.child(j) {
+                            if inner.kind() == "user_type" {
+                                return get_user_type_name(inner, text);
+                            }
+                        }
+                    }
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+fn get_user_type_name(user_type: Node, text: &str) -> Option<String> {
+    let mut name: Option<String> = None;
+    for i in 0..user_type.child_count() {
+        if let Some(child) = user_type.child(i) {
+            if child.kind() == "type_identifier" {
+                name = Some(text.slice(child.byte_range()).to_string());
+            }
+        }
+    }
+    name
+}
+
+impl LanguageParser for KotlinParser {
+    fn get_parser(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    fn get_parser_query(&self) -> &String {
+        &KOTLIN_PARSER_QUERY
+    }
+
+    fn get_parser_query_find_all(&self) -> &String {
+        &KOTLIN_PARSER_QUERY_FIND_ALL
+    }
+
+    fn get_namespace(&self, mut parent: Option<Node>, text: &str) -> Vec<String> {
+        let mut namespaces: Vec<String> = vec![];
+        while parent.is_some() {
+            match parent.unwrap().kind() {
+                "class_declaration" | "object_declaration" => {
+                    if let Some(name) = get_child_text_by_kind(parent.unwrap(), "type_identifier", text) {
+                        namespaces.push(name);
+                    }
+                }
+                "companion_object" => {
+                    let name = get_child_text_by_kind(parent.unwrap(), "type_identifier", text)
+                        .unwrap_or("Companion".to_string());
+                    namespaces.push(name);
+                }
+                _ => {}
+            }
+            parent = parent.unwrap().parent();
+        }
+        namespaces.reverse();
+        namespaces
+    }
+
+    fn get_extra_declarations_for_struct(&mut self, struct_name: String, tree: &Tree, code: &str, path: &PathBuf) -> Vec<SymbolInfo> {
+        let mut res: Vec<SymbolInfo> = vec![];
+        let mut qcursor = tree_sitter::QueryCursor::new();
+        let query = Query::new(self.get_parser().language().unwrap(), "((function_declaration) @function)").unwrap();
+        let matches = qcursor.matches(&query, tree.root_node(), code.as_bytes());
+        for match_ in matches {
+            for capture in match_.captures {
+                if get_receiver_type_name(capture.node, code).as_ref() == Some(&struct_name) {
+                    res.push(SymbolInfo {
+                        path: path.clone(),
+                        range: capture.node.range(),
+                    })
+                }
+            }
+        }
+        res
+    }
+
+    fn get_variable(&mut self, captures: &[QueryCapture], query: &Query, code: &str) -> Option<VariableInfo> {
+        let mut var = VariableInfo {
+            name: "".to_string(),
+            range: Range {
+                start_byte: 0,
+                end_byte: 0,
+                start_point: Default::default(),
+                end_point: Default::default(),
+            },
+            type_names: vec![],
+            meta_path: None,
+        };
+        for capture in captures {
+            let capture_name = &query.capture_names()[capture.index as usize];
+            match capture_name.as_str() {
+                "variable" => {
+                    var.range = capture.node.range();
+                    if let Some(var_type) = try_to_find_type(&mut self.parser, &capture.node, code) {
+                        var.type_names.push(var_type);
+                    }
+                }
+                "variable_name" => {
+                    let text = code.slice(capture.node.byte_range());
+                    var.name = text.to_string();
+                }
+                &_ => {}
+            }
+        }
+
+        if var.name.is_empty() {
+            return None;
+        }
+
+        Some(var)
+    }
+
+    fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        let name = get_child_text_by_kind(parent, "simple_identifier", text).unwrap_or_default();
+        let scope = get_receiver_type_name(parent, text).into_iter().collect();
+        (name, scope)
+    }
+
+    fn get_variable_name(&self, parent: Node, text: &str) -> String {
+        for i in 0..parent.child_count() {
+            if let Some(child) = parent.child(i) {
+                let kind = child.kind();
+                match kind {
+                    "variable_declaration" => {
+                        if let Some(name) = get_child_text_by_kind(child, "simple_identifier", text) {
+                            return name;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        return "".to_string();
+    }
+}
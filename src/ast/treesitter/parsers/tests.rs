@@ -5,6 +5,9 @@ use crate::ast::treesitter::parsers::LanguageParser;
 use crate::ast::treesitter::structs::{SymbolDeclarationStruct, UsageSymbolInfo};
 
 mod cpp;
+mod csharp;
+mod go;
+mod kotlin;
 mod rust;
 
 pub(crate) fn test_query_function(mut parser: Box<dyn LanguageParser>,
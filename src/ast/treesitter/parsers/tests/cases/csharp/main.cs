@@ -0,0 +1,75 @@
+using System;
+using System.Collections.Generic;
+
+namespace Geometry
+{
+    public interface IShape
+    {
+        double Area();
+        string Name { get; }
+    }
+
+    public enum Color
+    {
+        Red,
+        Green,
+        Blue
+    }
+
+    public struct Point
+    {
+        public int X;
+        public int Y;
+
+        public Point(int x, int y)
+        {
+            X = x;
+            Y = y;
+        }
+    }
+
+    // Circle is a shape with a center and a radius
+    public class Circle : IShape
+    {
+        private Point center;
+        public double Radius { get; set; }
+        public string Name => "circle";
+
+        public Circle(Point center, double radius)
+        {
+            this.center = center;
+            Radius = radius;
+        }
+
+        public double Area()
+        {
+            return Math.PI * Radius * Radius;
+        }
+
+        public class Builder
+        {
+            private double radius = 1.0;
+
+            public Circle Build()
+            {
+                Point origin = new Point(0, 0);
+                var circle = new Circle(origin, radius);
+                return circle;
+            }
+        }
+    }
+}
+
+namespace App
+{
+    class Program
+    {
+        static void Main(string[] args)
+        {
+            var shapes = new List<IShape>();
+            Geometry.Circle circle = new Geometry.Circle.Builder().Build();
+            shapes.Add(circle);
+            Console.WriteLine("area: " + circle.Area());
+        }
+    }
+}
@@ -0,0 +1,58 @@
+package main
+
+import (
+	"fmt"
+	"math"
+)
+
+// Version of the sample program
+const Version = "1.0.0"
+
+var (
+	defaultOrigin = Point{X: 0, Y: 0}
+	counter       int
+)
+
+/* Point is a point on a plane */
+type Point struct {
+	X, Y float64
+	Label string
+}
+
+// Shape is anything that has an area
+type Shape interface {
+	Area() float64
+	Perimeter() float64
+}
+
+type Circle struct {
+	Center *Point
+	Radius float64
+}
+
+// Distance calculates the Euclidean distance
+func (p Point) Distance(other Point) float64 {
+	dx := p.X - other.X
+	dy := p.Y - other.Y
+	return math.Sqrt(dx*dx + dy*dy)
+}
+
+func (c *Circle) Area() float64 {
+	return math.Pi * c.Radius * c.Radius
+}
+
+func (c *Circle) Perimeter() float64 {
+	return 2 * math.Pi * c.Radius
+}
+
+func describe(s Shape) string {
+	return fmt.Sprintf("area=%f perimeter=%f", s.Area(), s.Perimeter())
+}
+
+func main() {
+	var circle Shape = &Circle{Center: &defaultOrigin, Radius: 2}
+	p := Point{X: 3, Y: 4}
+	d := p.Distance(defaultOrigin)
+	fmt.Println(describe(circle), d)
+	counter++
+}
@@ -0,0 +1,45 @@
+package geometry
+
+import kotlin.math.PI
+
+val DEFAULT_RADIUS = 1.0
+
+// a point on a plane
+data class Point(val x: Int, val y: Int)
+
+interface Shape {
+    fun area(): Double
+}
+
+class Circle(private val center: Point, private val radius: Double) : Shape {
+    var label: String = "circle"
+
+    override fun area(): Double {
+        return PI * radius * radius
+    }
+
+    companion object {
+        fun unit(): Circle {
+            val origin = Point(0, 0)
+            return Circle(origin, DEFAULT_RADIUS)
+        }
+    }
+}
+
+object Registry {
+    private val shapes: MutableList<Shape> = mutableListOf()
+
+    fun register(shape: Shape) {
+        shapes.add(shape)
+    }
+}
+
+fun Circle.describe(): String {
+    return "circle with area " + area()
+}
+
+fun main() {
+    val circle = Circle.unit()
+    Registry.register(circle)
+    println(circle.describe())
+}
@@ -0,0 +1,20 @@
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::ast::treesitter::parsers::go::GoParser;
+    use crate::ast::treesitter::parsers::tests::test_query_function;
+
+    const MAIN_GO_CODE: &str = include_str!("cases/go/main.go");
+    const MAIN_GO_INDEXES: &str = include_str!("cases/go/main.go.indexes.json");
+    const MAIN_GO_USAGES: &str = include_str!("cases/go/main.go.usages.json");
+
+    #[test]
+    fn test_query_go_function() {
+        let parser = Box::new(GoParser::new().expect("GoParser::new"));
+        let path = PathBuf::from("main.go");
+        test_query_function(parser, &path, MAIN_GO_CODE,
+                            serde_json::from_str(MAIN_GO_INDEXES).unwrap(),
+                            serde_json::from_str(MAIN_GO_USAGES).unwrap());
+    }
+}
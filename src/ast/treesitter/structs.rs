@@ -41,6 +41,13 @@ pub trait UsageSymbolInfo: Debug + Send + Sync {
     fn get_range(&self) -> Range;
     fn set_definition_meta_path(&mut self, meta_path: String);
     fn get_declaration_meta_path(&self) -> Option<String>;
+    fn clone_box(&self) -> Box<dyn UsageSymbolInfo>;
+}
+
+impl Clone for Box<dyn UsageSymbolInfo> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
 
@@ -82,6 +89,10 @@ impl UsageSymbolInfo for VariableInfo {
     fn get_declaration_meta_path(&self) -> Option<String> {
         self.meta_path.clone()
     }
+
+    fn clone_box(&self) -> Box<dyn UsageSymbolInfo> {
+        Box::new(self.clone())
+    }
 }
 
 #[derive(DynPartialEq, PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -120,6 +131,10 @@ impl UsageSymbolInfo for FunctionCallInfo {
     fn get_declaration_meta_path(&self) -> Option<String> {
         self.meta_path.clone()
     }
+
+    fn clone_box(&self) -> Box<dyn UsageSymbolInfo> {
+        Box::new(self.clone())
+    }
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -161,6 +176,10 @@ impl UsageSymbolInfo for StaticInfo {
     fn get_declaration_meta_path(&self) -> Option<String> {
         self.meta_path.clone()
     }
+
+    fn clone_box(&self) -> Box<dyn UsageSymbolInfo> {
+        Box::new(self.clone())
+    }
 }
 
 
@@ -171,6 +190,7 @@ pub enum SymbolType {
     Class,
     Enum,
     Method,
+    Field,
     Unknown,
 }
 
@@ -183,6 +203,7 @@ impl FromStr for SymbolType {
             "class" => SymbolType::Class,
             "global_var" => SymbolType::GlobalVar,
             "function" => SymbolType::Function,
+            "field" => SymbolType::Field,
             _ => SymbolType::Unknown
         });
     }
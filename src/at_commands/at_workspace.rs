@@ -21,7 +21,7 @@ impl AtWorkspace {
     }
 }
 
-fn results2message(results: &Vec<Record>) -> ChatMessage {
+pub fn results2message(results: &Vec<Record>) -> ChatMessage {
     let mut vector_of_context_file: Vec<ContextFile> = vec![];
     for i in 0..results.len() {
         let r = &results[i];
@@ -46,6 +46,8 @@ pub async fn start_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) -> Backgro
         tokio::spawn(basic_transmit::telemetry_background_task(gcx.clone())),
         tokio::spawn(snippets_transmit::tele_snip_background_task(gcx.clone())),
         tokio::spawn(vecdb::vecdb::vecdb_background_reload(gcx.clone())),   // this in turn can create global_context::vec_db
+        tokio::spawn(crate::files_in_workspace::file_watcher_background_task(gcx.clone())),
+        tokio::spawn(crate::global_context::caps_version_background_task(gcx.clone())),
     ]);
     match *gcx.clone().read().await.ast_module.lock().await {
         Some(ref ast) => bg.extend(ast.ast_start_background_tasks().await),
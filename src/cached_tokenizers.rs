@@ -1,5 +1,7 @@
 use tokio::io::AsyncWriteExt;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, RwLock as StdRwLock};
 use std::time::Duration;
 use tokio::sync::RwLock as ARwLock;
@@ -7,7 +9,7 @@ use tokio::sync::Mutex as AMutex;
 use tokenizers::Tokenizer;
 use reqwest::header::AUTHORIZATION;
 use reqwest::Response;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::global_context::GlobalContext;
@@ -119,6 +121,107 @@ async fn try_download_tokenizer_file_and_open(
     Err("failed to download tokenizer".to_string())
 }
 
+// Next cached_tokenizer() call loads these again, from disk or, for `redownload`, from the new location
+pub async fn forget_tokenizers(
+    global_context: Arc<ARwLock<GlobalContext>>,
+    models: &HashSet<String>,
+    redownload: &HashSet<String>,
+) {
+    let tokenizer_download_lock: Arc<AMutex<bool>> = global_context.read().await.tokenizer_download_lock.clone();
+    let _tokenizer_download_locked = tokenizer_download_lock.lock().await;
+    let cache_dir = {
+        let mut cx_locked = global_context.write().await;
+        cx_locked.tokenizer_map.retain(|model_name, _| !models.contains(model_name));
+        cx_locked.tokenizer_approximate_retry_ts.retain(|model_name, _| !models.contains(model_name));
+        cx_locked.cache_dir.clone()
+    };
+    for model_name in redownload {
+        let path = cache_dir.join("tokenizers").join(model_name).join("tokenizer.json");
+        if path.exists() {
+            info!("removing outdated tokenizer {}", path.display());
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                error!("failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// Every piece of up to 3 characters is one token. BPE vocabularies give 3.5-4 characters per token for code,
+// so the prompt errs on the short side and fits into n_ctx. Special tokens like <fim_prefix> stay one piece,
+// but all the pieces are the same unknown token: FIM scratchpads can't tell a sentinel, they refuse to work.
+const APPROXIMATE_TOKENIZER_JSON: &str = r#"{
+    "version": "1.0",
+    "truncation": null,
+    "padding": null,
+    "added_tokens": [],
+    "normalizer": null,
+    "pre_tokenizer": {"type": "Split", "pattern": {"Regex": "<[^<>\\s]{1,32}>|[\\s\\S]{1,3}"}, "behavior": "Isolated", "invert": false},
+    "post_processor": null,
+    "decoder": null,
+    "model": {"type": "WordLevel", "vocab": {"[UNK]": 0}, "unk_token": "[UNK]"}
+}"#;
+
+// Real tokenizers are tried again after this, maybe the network is back
+const APPROXIMATE_TOKENIZER_RETRY_SECS: u64 = 300;
+
+pub fn approximate_tokenizer() -> Result<Tokenizer, String> {
+    Tokenizer::from_str(APPROXIMATE_TOKENIZER_JSON).map_err(|e| format!("failed to create approximate tokenizer: {}", e))
+}
+
+pub fn is_approximate_tokenizer(tokenizer: &Tokenizer) -> bool {
+    tokenizer.get_vocab_size(true) == 1 && tokenizer.token_to_id("[UNK]") == Some(0)
+}
+
+fn _find_in_tokenizers_dir(tokenizers_dir: &String, names: &[&String]) -> Option<PathBuf> {
+    if tokenizers_dir.is_empty() {
+        return None;
+    }
+    for name in names {
+        let path = PathBuf::from(tokenizers_dir).join(name).join("tokenizer.json");
+        if path.exists() && check_json_file(&path) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+// A bundle is a directory with the same layout as the cache: <model>/tokenizer.json, model names can have slashes
+pub async fn import_tokenizer_bundle(
+    bundle_dir: &PathBuf,
+    cache_dir: &PathBuf,
+) -> Result<usize, String> {
+    let mut found: Vec<PathBuf> = vec![];
+    let mut dirs: Vec<PathBuf> = vec![bundle_dir.clone()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).map_err(|e| format!("failed to read {}: {}", dir.display(), e))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.file_name().map(|x| x == "tokenizer.json").unwrap_or(false) {
+                found.push(path);
+            }
+        }
+    }
+    let mut imported = 0;
+    for path in found {
+        let relative = path.strip_prefix(bundle_dir).unwrap();
+        if relative.parent().map(|x| x.as_os_str().is_empty()).unwrap_or(true) {
+            continue;  // tokenizer.json right in the bundle dir, no model name
+        }
+        if !check_json_file(&path) {
+            error!("skipping {}: not a tokenizer", path.display());
+            continue;
+        }
+        let to = cache_dir.join("tokenizers").join(relative);
+        tokio::fs::create_dir_all(to.parent().unwrap()).await.map_err(|e| format!("failed to create parent dir: {}", e))?;
+        tokio::fs::copy(&path, &to).await.map_err(|e| format!("failed to copy {}: {}", path.display(), e))?;
+        info!("imported tokenizer {}", to.display());
+        imported += 1;
+    }
+    Ok(imported)
+}
+
 pub async fn cached_tokenizer(
     caps: Arc<StdRwLock<CodeAssistantCaps>>,
     global_context: Arc<ARwLock<GlobalContext>>,
@@ -127,12 +230,13 @@ pub async fn cached_tokenizer(
     let tokenizer_download_lock: Arc<AMutex<bool>> = global_context.read().await.tokenizer_download_lock.clone();
     let _tokenizer_download_locked = tokenizer_download_lock.lock().await;
 
-    let (client2, cache_dir, tokenizer_arc, api_key) = {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let (client2, cache_dir, tokenizer_arc, retry_ts, api_key, tokenizers_dir) = {
         let cx_locked = global_context.read().await;
-        (cx_locked.http_client.clone(), cx_locked.cache_dir.clone(), cx_locked.tokenizer_map.clone().get(&model_name).cloned(), cx_locked.cmdline.api_key.clone())
+        (cx_locked.http_client.clone(), cx_locked.cache_dir.clone(), cx_locked.tokenizer_map.clone().get(&model_name).cloned(), cx_locked.tokenizer_approximate_retry_ts.get(&model_name).cloned(), cx_locked.cmdline.api_key.clone(), cx_locked.cmdline.tokenizers_dir.clone())
     };
 
-    if tokenizer_arc.is_some() {
+    if tokenizer_arc.is_some() && retry_ts.map(|x| now < x).unwrap_or(true) {
         return Ok(tokenizer_arc.unwrap().clone())
     }
 
@@ -141,16 +245,58 @@ pub async fn cached_tokenizer(
         .await
         .expect("failed to create cache dir");
     let to = tokenizer_cache_dir.join(model_name.clone()).join("tokenizer.json");
-    let http_path = {
+    let (http_path, rewritten_model_name) = {
         let caps_locked = caps.read().unwrap();
-        let rewritten_model_name = caps_locked.tokenizer_rewrite_path.get(&model_name).unwrap_or(&model_name);
-        caps_locked.tokenizer_path_template.replace("$MODEL", rewritten_model_name)
+        let rewritten_model_name = caps_locked.tokenizer_rewrite_path.get(&model_name).unwrap_or(&model_name).clone();
+        (caps_locked.tokenizer_path_template.replace("$MODEL", &rewritten_model_name), rewritten_model_name)
+    };
+    let mut approximate = false;
+    let tokenizer = if let Some(local_path) = _find_in_tokenizers_dir(&tokenizers_dir, &[&model_name, &rewritten_model_name]) {
+        info!("loading tokenizer \"{}\"", local_path.display());
+        Tokenizer::from_file(local_path).map_err(|e| format!("failed to load tokenizer: {}", e))?
+    } else {
+        match try_download_tokenizer_file_and_open(&client2, http_path.as_str(), api_key.clone(), &to).await {
+            Ok(()) => {
+                info!("loading tokenizer \"{}\"", to.display());
+                Tokenizer::from_file(to).map_err(|e| format!("failed to load tokenizer: {}", e))?
+            }
+            Err(e) => {
+                if let Some(arc) = tokenizer_arc {
+                    // still no luck, keep the approximate one for a while longer
+                    global_context.write().await.tokenizer_approximate_retry_ts.insert(model_name.clone(), now + APPROXIMATE_TOKENIZER_RETRY_SECS);
+                    return Ok(arc);
+                }
+                warn!("{} for \"{}\", falling back to approximate token counts, will try again in {}s", e, model_name, APPROXIMATE_TOKENIZER_RETRY_SECS);
+                approximate = true;
+                approximate_tokenizer()?
+            }
+        }
     };
-    try_download_tokenizer_file_and_open(&client2, http_path.as_str(), api_key.clone(), &to).await?;
-    info!("loading tokenizer \"{}\"", to.display());
-    let tokenizer = Tokenizer::from_file(to).map_err(|e| format!("failed to load tokenizer: {}", e))?;
     let arc = Arc::new(StdRwLock::new(tokenizer));
 
-    global_context.write().await.tokenizer_map.insert(model_name.clone(), arc.clone());
+    let mut cx_locked = global_context.write().await;
+    cx_locked.tokenizer_map.insert(model_name.clone(), arc.clone());
+    if approximate {
+        cx_locked.tokenizer_approximate_retry_ts.insert(model_name.clone(), now + APPROXIMATE_TOKENIZER_RETRY_SECS);
+    } else {
+        cx_locked.tokenizer_approximate_retry_ts.remove(&model_name);
+    }
     Ok(arc)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approximate_tokenizer() {
+        let tokenizer = approximate_tokenizer().unwrap();
+        let count = |text: &str| tokenizer.encode(text, false).unwrap().len();
+        assert_eq!(count("def hello_world():"), 6);
+        assert_eq!(count("<fim_prefix>"), 1);
+        assert_eq!(count("<|endoftext|>"), 1);
+        assert_eq!(count(""), 0);
+        assert!(is_approximate_tokenizer(&tokenizer));
+    }
+}
@@ -27,6 +27,8 @@ pub struct SamplingParameters {
     pub temperature: Option<f32>,
     pub top_p: Option<f32>,
     pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<usize>,  // number of candidates, not every endpoint supports it
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -46,8 +48,16 @@ pub struct CodeCompletionPost {
     pub use_ast: bool,
     #[serde(default)]
     pub use_vecdb: bool,
+    // Post-processing needs the whole completion, so with stream it comes in one piece at the end. Set this
+    // to get deltas as they arrive, at the cost of extra brackets and lines repeating the code after the cursor.
+    #[serde(default)]
+    pub no_postprocess: bool,
+    #[serde(default)]
+    pub client_id: String,  // a newer request from the same client for the same file cancels this one
 }
 
+pub const MAX_COMPLETION_CHOICES: usize = 10;
+
 pub(crate) fn validate_post(code_completion_post: CodeCompletionPost) -> axum::response::Result<(), ScratchError> {
     let pos = code_completion_post.inputs.cursor.clone();
     let Some(source) = code_completion_post.inputs.sources.get(&code_completion_post.inputs.cursor.file) else {
@@ -63,6 +73,14 @@ pub(crate) fn validate_post(code_completion_post: CodeCompletionPost) -> axum::r
     if col > line.len_chars() {
         return Err(ScratchError::new(StatusCode::BAD_REQUEST, "invalid post".to_string()))
     }
+    if let Some(n) = code_completion_post.parameters.n {
+        if n == 0 || n > MAX_COMPLETION_CHOICES {
+            return Err(ScratchError::new(StatusCode::BAD_REQUEST, format!("n should be between 1 and {}", MAX_COMPLETION_CHOICES)))
+        }
+        if n > 1 && code_completion_post.stream {
+            return Err(ScratchError::new(StatusCode::BAD_REQUEST, "n > 1 is not supported with stream".to_string()))
+        }
+    }
     Ok(())
 }
 
@@ -89,6 +107,7 @@ mod tests {
                 temperature: Some(0.1),
                 top_p: None,
                 stop: None,
+                n: None,
             },
             model: "".to_string(),
             scratchpad: "".to_string(),
@@ -96,6 +115,8 @@ mod tests {
             no_cache: false,
             use_ast: true,
             use_vecdb: true,
+            no_postprocess: false,
+            client_id: "".to_string(),
         };
         assert!(validate_post(post).is_ok());
     }
@@ -117,6 +138,7 @@ mod tests {
                 temperature: Some(0.1),
                 top_p: None,
                 stop: None,
+                n: None,
             },
             model: "".to_string(),
             scratchpad: "".to_string(),
@@ -124,6 +146,8 @@ mod tests {
             no_cache: false,
             use_ast: true,
             use_vecdb: true,
+            no_postprocess: false,
+            client_id: "".to_string(),
         };
         assert!(validate_post(post).is_ok());
     }
@@ -145,6 +169,7 @@ mod tests {
                 temperature: Some(0.1),
                 top_p: None,
                 stop: None,
+                n: None,
             },
             model: "".to_string(),
             scratchpad: "".to_string(),
@@ -152,6 +177,8 @@ mod tests {
             no_cache: false,
             use_ast: true,
             use_vecdb: true,
+            no_postprocess: false,
+            client_id: "".to_string(),
         };
         assert!(validate_post(post).is_err());
     }
@@ -173,6 +200,7 @@ mod tests {
                 temperature: Some(0.1),
                 top_p: None,
                 stop: None,
+                n: None,
             },
             model: "".to_string(),
             scratchpad: "".to_string(),
@@ -180,9 +208,48 @@ mod tests {
             no_cache: false,
             use_ast: true,
             use_vecdb: true,
+            no_postprocess: false,
+            client_id: "".to_string(),
         };
         assert!(validate_post(post).is_err());
     }
+
+    #[test]
+    fn test_invalid_post_n_choices_stream() {
+        let post = CodeCompletionPost {
+            inputs: CodeCompletionInputs {
+                sources: HashMap::from_iter([("hello.py".to_string(), "def hello_world():".to_string())]),
+                cursor: CursorPosition {
+                    file: "hello.py".to_string(),
+                    line: 0,
+                    character: 18,
+                },
+                multiline: true,
+            },
+            parameters: SamplingParameters {
+                max_new_tokens: 20,
+                temperature: Some(0.1),
+                top_p: None,
+                stop: None,
+                n: Some(3),
+            },
+            model: "".to_string(),
+            scratchpad: "".to_string(),
+            stream: true,
+            no_cache: false,
+            use_ast: false,
+            use_vecdb: false,
+            no_postprocess: false,
+            client_id: "".to_string(),
+        };
+        assert!(validate_post(post.clone()).is_err());
+        let mut post_not_stream = post.clone();
+        post_not_stream.stream = false;
+        assert!(validate_post(post_not_stream).is_ok());
+        let mut post_one_choice = post;
+        post_one_choice.parameters.n = Some(1);
+        assert!(validate_post(post_one_choice).is_ok());
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
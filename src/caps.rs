@@ -1,14 +1,15 @@
-use tracing::{info, error};
+use tracing::{info, error, warn};
 use serde::Deserialize;
 use serde::Serialize;
 use std::fs::File;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Read;
 use std::sync::Arc;
 use std::sync::RwLock as StdRwLock;
 use tokio::sync::RwLock;
 use url::Url;
 use crate::global_context::GlobalContext;
+use crate::caps_overlay;
 use crate::known_models::KNOWN_MODELS;
 
 const CAPS_FILENAME: &str = "coding_assistant_caps.json";
@@ -23,6 +24,61 @@ pub struct ModelRecord {
     pub default_scratchpad: String,
     #[serde(default)]
     pub similar_models: Vec<String>,
+    // empty means use the caps-wide endpoint
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub endpoint_style: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub endpoint_template: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub endpoint_chat_passthrough: String,
+    // name of the environment variable with the API key for this endpoint, empty means --api-key
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub api_key_env: String,
+}
+
+// Another way to serve the same request: a different endpoint for the same model. Empty fields are taken
+// from the model (or caps) that failed. Only endpoints can fall back, not models: the prompt is already
+// built for the model's tokenizer and FIM sentinels.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FallbackEndpoint {
+    #[serde(default)]
+    pub endpoint_style: String,
+    #[serde(default)]
+    pub endpoint_template: String,
+    #[serde(default)]
+    pub endpoint_chat_passthrough: String,
+    #[serde(default)]
+    pub api_key_env: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EndpointRetry {
+    pub max_retries: usize,           // per endpoint, after the first attempt
+    pub backoff_ms: u64,              // doubles with each retry
+    pub backoff_max_ms: u64,
+    pub first_response_timeout_ms: u64,  // streaming only, until the first event; 0 means wait forever
+    pub total_timeout_ms: u64,           // not streaming, the whole response with the body; 0 means wait forever
+}
+
+impl Default for EndpointRetry {
+    fn default() -> Self {
+        EndpointRetry {
+            max_retries: 1,
+            backoff_ms: 500,
+            backoff_max_ms: 4000,
+            first_response_timeout_ms: 60000,
+            total_timeout_ms: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ModelEndpoint {
+    pub endpoint_style: String,
+    pub endpoint_template: String,
+    pub endpoint_chat_passthrough: String,
+    pub api_key: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -65,12 +121,46 @@ pub struct CodeAssistantCaps {
     pub running_models: Vec<String>,
     #[serde(default)]
     pub caps_version: i64,  // need to reload if it increases on server, that happens when server configuration changes
+    #[serde(default)]
+    pub endpoint_retry: EndpointRetry,
+    #[serde(default)]
+    pub code_completion_fallbacks: Vec<FallbackEndpoint>,
+    #[serde(default)]
+    pub code_chat_fallbacks: Vec<FallbackEndpoint>,
+    #[serde(skip)]
+    pub caps_origin: String,
+    // ETag and Last-Modified of caps_origin, so polling asks the server to send caps only if they changed
+    #[serde(skip)]
+    pub caps_etag: String,
+    #[serde(skip)]
+    pub caps_last_modified: String,
+    // JSON pointer -> where the value came from, if not from caps_origin
+    #[serde(skip)]
+    pub field_origin: HashMap<String, String>,
 }
 
-pub async fn load_caps(
-    cmdline: crate::global_context::CommandLine,
+#[derive(Debug, Deserialize)]
+struct CapsVersionOnly {
+    #[serde(default)]
+    caps_version: i64,
+}
+
+// Caps as they were read, before parsing
+pub struct CapsBuffer {
+    pub buffer: String,
+    pub caps_url: String,
+    pub etag: String,
+    pub last_modified: String,
+}
+
+// Reads the text of caps. With etag or last_modified of the caps read before, a remote server can answer
+// 304 Not Modified, then it's None.
+async fn _read_caps_buffer(
+    cmdline: &crate::global_context::CommandLine,
     global_context: Arc<RwLock<GlobalContext>>,
-) -> Result<Arc<StdRwLock<CodeAssistantCaps>>, String> {
+    etag: &str,
+    last_modified: &str,
+) -> Result<Option<CapsBuffer>, String> {
     let mut buffer = String::new();
     let mut is_local_file = false;
     let mut is_remote_address = false;
@@ -81,6 +171,12 @@ pub async fn load_caps(
     } else if cmdline.address_url == "HF" {
         buffer = HF_DEFAULT_CAPS.to_string();
         caps_url = "<compiled-in-caps-hf>".to_string();
+    } else if cmdline.address_url == "Ollama" {
+        buffer = OLLAMA_DEFAULT_CAPS.to_string();
+        caps_url = "<compiled-in-caps-ollama>".to_string();
+    } else if cmdline.address_url == "llama.cpp" {
+        buffer = LLAMA_CPP_DEFAULT_CAPS.to_string();
+        caps_url = "<compiled-in-caps-llama-cpp>".to_string();
     } else {
         if cmdline.address_url.starts_with("http") {
             is_remote_address = true;
@@ -103,15 +199,62 @@ pub async fn load_caps(
         if !api_key.is_empty() {
             headers.insert(reqwest::header::AUTHORIZATION, reqwest::header::HeaderValue::from_str(format!("Bearer {}", api_key).as_str()).unwrap());
         }
+        for (name, value) in [(reqwest::header::IF_NONE_MATCH, etag), (reqwest::header::IF_MODIFIED_SINCE, last_modified)] {
+            match reqwest::header::HeaderValue::from_str(value) {
+                Ok(value) if !value.is_empty() => { headers.insert(name, value); },
+                _ => {},
+            }
+        }
         let response = http_client.get(caps_url.clone()).headers(headers).send().await.map_err(|e| format!("{}", e))?;
         let status = response.status().as_u16();
+        if status == 304 {
+            return Ok(None);
+        }
+        let header_str = |name: reqwest::header::HeaderName| {
+            response.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("").to_string()
+        };
+        let (etag, last_modified) = (header_str(reqwest::header::ETAG), header_str(reqwest::header::LAST_MODIFIED));
         buffer = response.text().await.map_err(|e| format!("failed to read response: {}", e))?;
         if status != 200 {
             return Err(format!("server responded with: {}", buffer));
         }
+        return Ok(Some(CapsBuffer { buffer, caps_url, etag, last_modified }));
     }
+    Ok(Some(CapsBuffer { buffer, caps_url, etag: "".to_string(), last_modified: "".to_string() }))
+}
+
+// Cheap to poll: a server that knows ETag or Last-Modified sends nothing if caps didn't change, that's None.
+// Otherwise returns the version and the caps to give to load_caps_from_buffer, they don't need to be read again.
+pub async fn fetch_caps_if_changed(
+    cmdline: &crate::global_context::CommandLine,
+    global_context: Arc<RwLock<GlobalContext>>,
+    etag: &str,
+    last_modified: &str,
+) -> Result<Option<(i64, CapsBuffer)>, String> {
+    let caps_buffer = match _read_caps_buffer(cmdline, global_context, etag, last_modified).await? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let version: CapsVersionOnly = serde_json::from_str(&caps_buffer.buffer).map_err(|e| format!("failed to parse {}: {}", caps_buffer.caps_url, e))?;
+    Ok(Some((version.caps_version, caps_buffer)))
+}
+
+pub async fn load_caps(
+    cmdline: crate::global_context::CommandLine,
+    global_context: Arc<RwLock<GlobalContext>>,
+) -> Result<Arc<StdRwLock<CodeAssistantCaps>>, String> {
+    let caps_buffer = _read_caps_buffer(&cmdline, global_context.clone(), "", "").await?
+        .ok_or("server responded with 304 Not Modified to an unconditional request".to_string())?;
+    load_caps_from_buffer(caps_buffer, global_context).await
+}
+
+pub async fn load_caps_from_buffer(
+    caps_buffer: CapsBuffer,
+    global_context: Arc<RwLock<GlobalContext>>,
+) -> Result<Arc<StdRwLock<CodeAssistantCaps>>, String> {
+    let CapsBuffer { buffer, caps_url, etag, last_modified } = caps_buffer;
     info!("reading caps from {}", caps_url);
-    let r0: ModelsOnly = serde_json::from_str(&KNOWN_MODELS).map_err(|e| {
+    let mut r0: ModelsOnly = serde_json::from_str(&KNOWN_MODELS).map_err(|e| {
         let up_to_line = KNOWN_MODELS.lines().take(e.line()).collect::<Vec<&str>>().join("\n");
         error!("{}\nfailed to parse KNOWN_MODELS: {}", up_to_line, e);
         format!("failed to parse KNOWN_MODELS: {}", e)
@@ -121,7 +264,31 @@ pub async fn load_caps(
         error!("{}\nfailed to parse {}: {}", up_to_line, caps_url, e);
         format!("failed to parse {}: {}", caps_url, e)
     })?;
+    let mut field_origin: HashMap<String, String> = HashMap::new();
+    let cache_dir = global_context.read().await.cache_dir.clone();
+    if let Some((overlay_origin, overlay)) = caps_overlay::load_caps_overlay(&cache_dir)? {
+        // parsed once more as values, the structs above already told about syntax errors in a nicer way
+        let mut r0_value: serde_json::Value = serde_json::from_str(&KNOWN_MODELS).unwrap();
+        let mut r1_value: serde_json::Value = serde_json::from_str(&buffer).unwrap();
+        caps_overlay::apply_caps_overlay(&mut r0_value, &mut r1_value, &overlay, &overlay_origin, &mut field_origin);
+        r0 = serde_json::from_value(r0_value).map_err(|e| format!("KNOWN_MODELS with {}: {}", overlay_origin, e))?;
+        r1 = serde_json::from_value(r1_value).map_err(|e| format!("{} with {}: {}", caps_url, overlay_origin, e))?;
+    }
+    let own_models = r1.code_completion_models.keys().map(|k| caps_overlay::model_pointer("code_completion_models", k))
+        .chain(r1.code_chat_models.keys().map(|k| caps_overlay::model_pointer("code_chat_models", k)))
+        .collect::<Vec<_>>();
     _inherit_r1_from_r0(&mut r1, &r0);
+    let inherited_models = r1.code_completion_models.keys().map(|k| caps_overlay::model_pointer("code_completion_models", k))
+        .chain(r1.code_chat_models.keys().map(|k| caps_overlay::model_pointer("code_chat_models", k)))
+        .filter(|k| !own_models.contains(k))
+        .collect::<Vec<_>>();
+    for k in inherited_models {
+        field_origin.entry(k).or_insert(caps_overlay::ORIGIN_KNOWN_MODELS.to_string());
+    }
+    r1.caps_origin = caps_url.clone();
+    r1.caps_etag = etag;
+    r1.caps_last_modified = last_modified;
+    r1.field_origin = field_origin;
     r1.endpoint_template = relative_to_full_url(&caps_url, &r1.endpoint_template)?;
     r1.endpoint_chat_passthrough = relative_to_full_url(&caps_url, &r1.endpoint_chat_passthrough)?;
     r1.telemetry_basic_dest = relative_to_full_url(&caps_url, &r1.telemetry_basic_dest)?;
@@ -129,6 +296,14 @@ pub async fn load_caps(
     r1.telemetry_basic_retrieve_my_own = relative_to_full_url(&caps_url, &r1.telemetry_basic_retrieve_my_own)?;
     r1.endpoint_embeddings_template = relative_to_full_url(&caps_url, &r1.endpoint_embeddings_template)?;
     r1.tokenizer_path_template = relative_to_full_url(&caps_url, &r1.tokenizer_path_template)?;
+    for rec in r1.code_completion_models.values_mut().chain(r1.code_chat_models.values_mut()) {
+        rec.endpoint_template = relative_to_full_url(&caps_url, &rec.endpoint_template)?;
+        rec.endpoint_chat_passthrough = relative_to_full_url(&caps_url, &rec.endpoint_chat_passthrough)?;
+    }
+    for fallback in r1.code_completion_fallbacks.iter_mut().chain(r1.code_chat_fallbacks.iter_mut()) {
+        fallback.endpoint_template = relative_to_full_url(&caps_url, &fallback.endpoint_template)?;
+        fallback.endpoint_chat_passthrough = relative_to_full_url(&caps_url, &fallback.endpoint_chat_passthrough)?;
+    }
     info!("caps {} completion models", r1.code_completion_models.len());
     info!("caps default completion model: \"{}\"", r1.code_completion_default_model);
     info!("caps {} chat models", r1.code_chat_models.len());
@@ -167,19 +342,19 @@ fn _inherit_r1_from_r0(
             r1.code_chat_models.insert(k.to_string(), r0.code_chat_models[k].clone());
         }
     }
-    // clone to "similar_models"
+    // clone to "similar_models", unless the alias has a record of its own (caps overlay patches make one)
     let ccmodel_keys_copy = r1.code_completion_models.keys().cloned().collect::<Vec<String>>();
     for k in ccmodel_keys_copy {
         let model_rec = r1.code_completion_models[&k].clone();
         for similar_model in model_rec.similar_models.iter() {
-            r1.code_completion_models.insert(similar_model.to_string(), model_rec.clone());
+            r1.code_completion_models.entry(similar_model.to_string()).or_insert(model_rec.clone());
         }
     }
     let chatmodel_keys_copy = r1.code_chat_models.keys().cloned().collect::<Vec<String>>();
     for k in chatmodel_keys_copy {
         let model_rec = r1.code_chat_models[&k].clone();
         for similar_model in model_rec.similar_models.iter() {
-            r1.code_chat_models.insert(similar_model.to_string(), model_rec.clone());
+            r1.code_chat_models.entry(similar_model.to_string()).or_insert(model_rec.clone());
         }
     }
     r1.code_completion_models = r1.code_completion_models.clone().into_iter().filter(|(k, _)| r1.running_models.contains(&k)).collect();
@@ -198,6 +373,44 @@ fn _inherit_r1_from_r0(
     }
 }
 
+fn _tokenizer_url(caps: &CodeAssistantCaps, model_name: &String) -> String {
+    let rewritten_model_name = caps.tokenizer_rewrite_path.get(model_name).unwrap_or(model_name);
+    caps.tokenizer_path_template.replace("$MODEL", rewritten_model_name)
+}
+
+// Models that were added, removed or have a different record (or endpoint) in new caps, and the
+// subset of them that now download the tokenizer from a different place
+pub fn models_changed(
+    old: &CodeAssistantCaps,
+    new: &CodeAssistantCaps,
+) -> (HashSet<String>, HashSet<String>) {
+    let mut changed: HashSet<String> = HashSet::new();
+    let mut tokenizer_changed: HashSet<String> = HashSet::new();
+    for is_chat in [false, true] {
+        let (old_models, new_models) = if is_chat {
+            (&old.code_chat_models, &new.code_chat_models)
+        } else {
+            (&old.code_completion_models, &new.code_completion_models)
+        };
+        for name in old_models.keys().chain(new_models.keys()) {
+            let old_rec = old_models.get(name).map(|x| serde_json::to_value(x).unwrap());
+            let new_rec = new_models.get(name).map(|x| serde_json::to_value(x).unwrap());
+            let old_endpoint = _endpoint_with_defaults(old, old_models.get(name));
+            let new_endpoint = _endpoint_with_defaults(new, new_models.get(name));
+            if old_rec != new_rec
+                || old_endpoint.endpoint_style != new_endpoint.endpoint_style
+                || old_endpoint.endpoint_template != new_endpoint.endpoint_template {
+                changed.insert(name.clone());
+            }
+            if _tokenizer_url(old, name) != _tokenizer_url(new, name) {
+                changed.insert(name.clone());
+                tokenizer_changed.insert(name.clone());
+            }
+        }
+    }
+    (changed, tokenizer_changed)
+}
+
 pub fn which_model_to_use<'a>(
     models: &'a HashMap<String, ModelRecord>,
     user_wants_model: &str,
@@ -218,6 +431,112 @@ pub fn which_model_to_use<'a>(
     }
 }
 
+fn _find_model_record<'a>(
+    caps: &'a CodeAssistantCaps,
+    model_name: &str,
+    is_chat: bool,
+) -> Option<&'a ModelRecord> {
+    let (first, second) = if is_chat {
+        (&caps.code_chat_models, &caps.code_completion_models)
+    } else {
+        (&caps.code_completion_models, &caps.code_chat_models)
+    };
+    first.get(model_name).or_else(|| second.get(model_name))
+}
+
+fn _endpoint_with_defaults(
+    caps: &CodeAssistantCaps,
+    rec: Option<&ModelRecord>,
+) -> ModelEndpoint {
+    let pick = |own: Option<&String>, global: &String| {
+        own.filter(|x| !x.is_empty()).unwrap_or(global).clone()
+    };
+    ModelEndpoint {
+        endpoint_style: pick(rec.map(|r| &r.endpoint_style), &caps.endpoint_style),
+        endpoint_template: pick(rec.map(|r| &r.endpoint_template), &caps.endpoint_template),
+        endpoint_chat_passthrough: pick(rec.map(|r| &r.endpoint_chat_passthrough), &caps.endpoint_chat_passthrough),
+        api_key: String::new(),
+    }
+}
+
+fn _api_key_from_env(model_name: &str, env_name: &String, cmdline_api_key: &String) -> Result<String, String> {
+    if env_name.is_empty() {
+        return Ok(cmdline_api_key.clone());
+    }
+    std::env::var(env_name).map_err(|_| {
+        format!("model '{}' takes the API key from ${}, which is not set", model_name, env_name)
+    })
+}
+
+pub fn endpoint_for_model(
+    caps: &CodeAssistantCaps,
+    model_name: &str,
+    is_chat: bool,
+    cmdline_api_key: &String,
+) -> Result<ModelEndpoint, String> {
+    let rec = _find_model_record(caps, model_name, is_chat);
+    let mut endpoint = _endpoint_with_defaults(caps, rec);
+    endpoint.api_key = _api_key_from_env(model_name, &rec.map(|r| r.api_key_env.clone()).unwrap_or_default(), cmdline_api_key)?;
+    Ok(endpoint)
+}
+
+// The model itself first, then fallbacks in the order they are listed in caps, all for the same model. Fallbacks
+// that can't be used (missing API key) are skipped with a warning, so they don't break the primary endpoint.
+pub fn endpoints_with_fallbacks(
+    caps: &CodeAssistantCaps,
+    model_name: &str,
+    is_chat: bool,
+    cmdline_api_key: &String,
+) -> Result<Vec<(String, ModelEndpoint)>, String> {
+    let primary = endpoint_for_model(caps, model_name, is_chat, cmdline_api_key)?;
+    let mut result = vec![(model_name.to_string(), primary.clone())];
+    let fallbacks = if is_chat { &caps.code_chat_fallbacks } else { &caps.code_completion_fallbacks };
+    for fallback in fallbacks {
+        let api_key = if fallback.api_key_env.is_empty() {
+            Ok(primary.api_key.clone())
+        } else {
+            _api_key_from_env(model_name, &fallback.api_key_env, cmdline_api_key)
+        };
+        let mut endpoint = primary.clone();
+        endpoint.api_key = match api_key {
+            Ok(api_key) => api_key,
+            Err(e) => {
+                warn!("skipping fallback {:?}: {}", fallback, e);
+                continue;
+            }
+        };
+        if !fallback.endpoint_style.is_empty() { endpoint.endpoint_style = fallback.endpoint_style.clone(); }
+        if !fallback.endpoint_template.is_empty() { endpoint.endpoint_template = fallback.endpoint_template.clone(); }
+        if !fallback.endpoint_chat_passthrough.is_empty() { endpoint.endpoint_chat_passthrough = fallback.endpoint_chat_passthrough.clone(); }
+        result.push((model_name.to_string(), endpoint));
+    }
+    Ok(result)
+}
+
+// What /v1/caps shows: each model with the endpoint it will actually use, API keys are never included
+pub fn caps_merged_view(caps: &CodeAssistantCaps) -> serde_json::Value {
+    let mut merged = caps.clone();
+    for is_chat in [false, true] {
+        let names = if is_chat { merged.code_chat_models.keys() } else { merged.code_completion_models.keys() }
+            .cloned().collect::<Vec<_>>();
+        for name in names {
+            let endpoint = _endpoint_with_defaults(caps, _find_model_record(caps, &name, is_chat));
+            let models = if is_chat { &mut merged.code_chat_models } else { &mut merged.code_completion_models };
+            let rec = models.get_mut(&name).unwrap();
+            rec.endpoint_style = endpoint.endpoint_style;
+            rec.endpoint_template = endpoint.endpoint_template;
+            rec.endpoint_chat_passthrough = endpoint.endpoint_chat_passthrough;
+        }
+    }
+    let mut result = serde_json::to_value(&merged).unwrap();
+    // more specific pointers win: a model from KNOWN_MODELS can have a field patched by the overlay
+    result["caps_origin"] = serde_json::json!({
+        "default": caps.caps_origin,
+        "fields": caps.field_origin,
+    });
+    result
+}
+
 pub fn which_scratchpad_to_use<'a>(
     scratchpads: &'a HashMap<String, serde_json::Value>,
     user_wants_scratchpad: &str,
@@ -266,3 +585,67 @@ const HF_DEFAULT_CAPS: &str = r#"
     "running_models": ["bigcode/starcoder", "meta-llama/Llama-2-70b-chat-hf"]
 }
 "#;
+
+// Models are named the way ollama names them, so they are not in KNOWN_MODELS
+const OLLAMA_DEFAULT_CAPS: &str = r####"
+{
+    "cloud_name": "Ollama",
+    "endpoint_template": "http://127.0.0.1:11434/api/generate",
+    "endpoint_style": "ollama",
+    "tokenizer_path_template": "https://huggingface.co/$MODEL/resolve/main/tokenizer.json",
+    "tokenizer_rewrite_path": {
+        "starcoder2:3b": "bigcode/starcoder2-3b",
+        "deepseek-coder:6.7b-instruct": "deepseek-ai/deepseek-coder-6.7b-instruct"
+    },
+    "code_completion_models": {
+        "starcoder2:3b": {
+            "n_ctx": 4096,
+            "supports_scratchpads": {
+                "FIM-PSM": {},
+                "FIM-SPM": {}
+            },
+            "default_scratchpad": "FIM-PSM"
+        }
+    },
+    "code_completion_default_model": "starcoder2:3b",
+    "code_completion_n_ctx": 4096,
+    "code_chat_models": {
+        "deepseek-coder:6.7b-instruct": {
+            "n_ctx": 4096,
+            "supports_scratchpads": {
+                "CHAT-GENERIC": {
+                    "token_esc": "",
+                    "keyword_system": "",
+                    "keyword_user": "### Instruction:\n",
+                    "keyword_assistant": "### Response:\n",
+                    "stop_list": [],
+                    "default_system_message": "You are an AI programming assistant.",
+                    "eot": "<|EOT|>"
+                }
+            }
+        }
+    },
+    "code_chat_default_model": "deepseek-coder:6.7b-instruct",
+    "telemetry_basic_dest": "",
+    "running_models": ["starcoder2:3b", "deepseek-coder:6.7b-instruct"]
+}
+"####;
+
+// llama.cpp server runs whatever model it was started with, the names here pick scratchpads and tokenizers
+const LLAMA_CPP_DEFAULT_CAPS: &str = r#"
+{
+    "cloud_name": "llama.cpp",
+    "endpoint_template": "http://127.0.0.1:8080/completion",
+    "endpoint_style": "llama_cpp",
+    "tokenizer_path_template": "https://huggingface.co/$MODEL/resolve/main/tokenizer.json",
+    "tokenizer_rewrite_path": {
+        "starcoder2/3b/base": "bigcode/starcoder2-3b",
+        "deepseek-coder/6.7b/instruct": "deepseek-ai/deepseek-coder-6.7b-instruct"
+    },
+    "code_completion_default_model": "starcoder2/3b/base",
+    "code_completion_n_ctx": 4096,
+    "code_chat_default_model": "deepseek-coder/6.7b/instruct",
+    "telemetry_basic_dest": "",
+    "running_models": ["starcoder2/3b/base", "deepseek-coder/6.7b/instruct"]
+}
+"#;
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tracing::info;
+
+const CAPS_OVERLAY_FILENAMES: [&str; 3] = ["caps_overlay.yaml", "caps_overlay.yml", "caps_overlay.json"];
+const MODEL_SECTIONS: [&str; 2] = ["code_completion_models", "code_chat_models"];
+
+pub const ORIGIN_KNOWN_MODELS: &str = "known_models";
+
+
+// Overlay is a user-level file in the cache dir, it looks like caps, but every field is optional.
+// YAML is a superset of JSON, so one parser reads both.
+pub fn load_caps_overlay(cache_dir: &PathBuf) -> Result<Option<(String, Value)>, String> {
+    for filename in CAPS_OVERLAY_FILENAMES {
+        let path = cache_dir.join(filename);
+        if !path.exists() {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let overlay: Value = serde_yaml::from_str(&text).map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+        if !overlay.is_object() {
+            return Err(format!("{}: expected a mapping at the top level", path.display()));
+        }
+        info!("caps overlay from {}", path.display());
+        return Ok(Some((path.display().to_string(), overlay)));
+    }
+    Ok(None)
+}
+
+fn _pointer_escape(key: &str) -> String {
+    key.replace("~", "~0").replace("/", "~1")
+}
+
+// Objects are merged key by key, everything else (including arrays) is replaced. Every replaced
+// value is recorded in `origins` by its JSON pointer.
+pub fn deep_merge(
+    dst: &mut Value,
+    src: &Value,
+    path: &String,
+    origin: &String,
+    origins: &mut HashMap<String, String>,
+) {
+    match (dst.as_object_mut(), src.as_object()) {
+        (Some(dst_map), Some(src_map)) => {
+            for (k, v) in src_map {
+                let sub_path = format!("{}/{}", path, _pointer_escape(k));
+                match dst_map.get_mut(k) {
+                    Some(dst_v) if dst_v.is_object() && v.is_object() => deep_merge(dst_v, v, &sub_path, origin, origins),
+                    _ => {
+                        dst_map.insert(k.clone(), v.clone());
+                        origins.insert(sub_path, origin.clone());
+                    }
+                }
+            }
+        }
+        _ => {
+            *dst = src.clone();
+            origins.insert(path.clone(), origin.clone());
+        }
+    }
+}
+
+// Model patches go where the model is defined: the caps if it's there, otherwise KNOWN_MODELS, so
+// the patch doesn't shadow the known model with an incomplete record. A similar_models alias gets its
+// own full record in caps. Models nobody knows about are new models, they are added to caps and to
+// running_models.
+pub fn apply_caps_overlay(
+    known_models: &mut Value,
+    caps: &mut Value,
+    overlay: &Value,
+    origin: &String,
+    origins: &mut HashMap<String, String>,
+) {
+    let overlay_map = match overlay.as_object() {
+        Some(x) => x,
+        None => return,
+    };
+    for (k, v) in overlay_map {
+        let path = format!("/{}", _pointer_escape(k));
+        if !MODEL_SECTIONS.contains(&k.as_str()) || !v.is_object() {
+            deep_merge(&mut caps[k], v, &path, origin, origins);
+            continue;
+        }
+        for (model, patch) in v.as_object().unwrap() {
+            let model_path = format!("{}/{}", path, _pointer_escape(model));
+            let in_caps = caps.get(k).and_then(|x| x.get(model)).is_some();
+            let in_known_models = known_models.get(k).and_then(|x| x.get(model)).is_some();
+            if in_caps {
+                deep_merge(&mut caps[k][model], patch, &model_path, origin, origins);
+            } else if in_known_models {
+                deep_merge(&mut known_models[k][model], patch, &model_path, origin, origins);
+            } else if let Some(mut record) = _alias_record(caps.get(k), model).or_else(|| _alias_record(known_models.get(k), model)) {
+                // running_models lists aliases, the patch goes onto a copy of the record they are cloned from
+                deep_merge(&mut record, patch, &model_path, origin, origins);
+                if !caps.get(k).map(|x| x.is_object()).unwrap_or(false) {
+                    caps[k] = Value::Object(Default::default());
+                }
+                caps[k][model] = record;
+            } else {
+                if !caps.get(k).map(|x| x.is_object()).unwrap_or(false) {
+                    caps[k] = Value::Object(Default::default());
+                }
+                caps[k][model] = patch.clone();
+                origins.insert(model_path, origin.clone());
+                if !caps.get("running_models").map(|x| x.is_array()).unwrap_or(false) {
+                    caps["running_models"] = Value::Array(vec![]);
+                }
+                let running = caps["running_models"].as_array_mut().unwrap();
+                if !running.contains(&Value::String(model.clone())) {
+                    running.push(Value::String(model.clone()));
+                }
+            }
+        }
+    }
+}
+
+// The record a similar_models alias is cloned from, without similar_models, so the clone doesn't spread further
+fn _alias_record(section: Option<&Value>, model: &str) -> Option<Value> {
+    let (_, record) = section?.as_object()?.iter().find(|(_, rec)| {
+        rec.get("similar_models").and_then(|x| x.as_array())
+            .map(|similar| similar.iter().any(|x| x.as_str() == Some(model)))
+            .unwrap_or(false)
+    })?;
+    let mut record = record.clone();
+    record.as_object_mut()?.remove("similar_models");
+    Some(record)
+}
+
+pub fn model_pointer(section: &str, model: &str) -> String {
+    format!("/{}/{}", section, _pointer_escape(model))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_apply_caps_overlay() {
+        let mut known_models = json!({
+            "code_completion_models": {
+                "bigcode/starcoder": {"n_ctx": 4096, "supports_scratchpads": {"FIM-PSM": {}, "FIM-SPM": {}}}
+            },
+            "code_chat_models": {},
+        });
+        let mut caps = json!({
+            "cloud_name": "Refact",
+            "code_completion_n_ctx": 2048,
+            "code_chat_models": {"gpt-4": {"n_ctx": 8192, "supports_scratchpads": {"PASSTHROUGH": {}}}},
+            "running_models": ["bigcode/starcoder", "gpt-4"],
+        });
+        let overlay: Value = serde_yaml::from_str(r#"
+code_completion_n_ctx: 4096
+code_completion_models:
+  bigcode/starcoder:
+    supports_scratchpads:
+      FIM-PSM:
+        fim_prefix: "<PRE>"
+code_chat_models:
+  gpt-4:
+    n_ctx: 4096
+  my-chat:
+    n_ctx: 2048
+    supports_scratchpads:
+      PASSTHROUGH: {}
+"#).unwrap();
+        let origin = "overlay.yaml".to_string();
+        let mut origins = HashMap::new();
+        apply_caps_overlay(&mut known_models, &mut caps, &overlay, &origin, &mut origins);
+
+        assert_eq!(caps["code_completion_n_ctx"], json!(4096));
+        assert_eq!(caps["code_chat_models"]["gpt-4"], json!({"n_ctx": 4096, "supports_scratchpads": {"PASSTHROUGH": {}}}));
+        assert_eq!(caps["code_chat_models"]["my-chat"]["n_ctx"], json!(2048));
+        assert_eq!(caps["running_models"], json!(["bigcode/starcoder", "gpt-4", "my-chat"]));
+        assert!(caps.get("code_completion_models").is_none());
+        assert_eq!(known_models["code_completion_models"]["bigcode/starcoder"]["supports_scratchpads"],
+                   json!({"FIM-PSM": {"fim_prefix": "<PRE>"}, "FIM-SPM": {}}));
+
+        let mut keys = origins.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec![
+            "/code_chat_models/gpt-4/n_ctx",
+            "/code_chat_models/my-chat",
+            "/code_completion_models/bigcode~1starcoder/supports_scratchpads/FIM-PSM/fim_prefix",
+            "/code_completion_n_ctx",
+        ]);
+    }
+
+    #[test]
+    fn test_patch_alias() {
+        let mut known_models = json!({
+            "code_completion_models": {
+                "bigcode/starcoder2-3b": {
+                    "n_ctx": 4096,
+                    "supports_scratchpads": {"FIM-REPO": {}},
+                    "default_scratchpad": "FIM-REPO",
+                    "similar_models": ["starcoder2/3b/base", "starcoder2/3b/vllm"],
+                }
+            },
+        });
+        let mut caps = json!({"running_models": ["starcoder2/3b/base"]});
+        let overlay: Value = serde_yaml::from_str(r#"
+code_completion_models:
+  starcoder2/3b/base:
+    n_ctx: 8192
+"#).unwrap();
+        let origin = "overlay.yaml".to_string();
+        let mut origins = HashMap::new();
+        apply_caps_overlay(&mut known_models, &mut caps, &overlay, &origin, &mut origins);
+
+        assert_eq!(caps["code_completion_models"]["starcoder2/3b/base"], json!({
+            "n_ctx": 8192,
+            "supports_scratchpads": {"FIM-REPO": {}},
+            "default_scratchpad": "FIM-REPO",
+        }));
+        assert_eq!(known_models["code_completion_models"]["bigcode/starcoder2-3b"]["n_ctx"], json!(4096));
+        assert_eq!(caps["running_models"], json!(["starcoder2/3b/base"]));
+        assert_eq!(origins.keys().collect::<Vec<_>>(), vec!["/code_completion_models/starcoder2~13b~1base/n_ctx"]);
+    }
+}
@@ -1,13 +1,16 @@
 use crate::call_validation::CodeCompletionPost;
+use crate::completion_cache_disk::CompletionCacheDisk;
 use std::sync::Arc;
 use std::sync::RwLock as StdRwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use ropey::Rope;
+use tracing::warn;
 // use tracing::info;
 
 const CACHE_ENTRIES: usize = 500;
 const CACHE_KEY_CHARS: usize = 5000;  // max memory CACHE_KEY_CHARS * CACHE_ENTRIES = 2500000 = 2.5M
+const CACHE_TYPED_AHEAD_ENTRIES: usize = 16;  // typing ahead continues one of the latest completions, older ones are not worth a scan on every miss
 
 
 // aggregate this struct in scratchpad to save cache
@@ -18,6 +21,8 @@ pub struct CompletionSaveToCache {
     pub completion0_text: String,
     pub completion0_finish_reason: String,
     pub completion0_snippet_telemetry_id: Option<u64>,
+    pub other_completions: Vec<(String, String)>,  // the rest of the ranked candidates, text and finish reason
+    pub other_snippet_telemetry_ids: Vec<u64>,
     pub model: String,
 }
 
@@ -32,6 +37,8 @@ impl CompletionSaveToCache {
             completion0_text: String::new(),
             completion0_finish_reason: String::new(),
             completion0_snippet_telemetry_id: None,
+            other_completions: Vec::new(),
+            other_snippet_telemetry_ids: Vec::new(),
             model: post.model.clone(),
         }
     }
@@ -42,12 +49,13 @@ impl CompletionSaveToCache {
 pub struct CompletionCache {
     pub map: HashMap<(String, String), serde_json::Value>,
     pub in_added_order: Vec<(String, String)>,
+    pub disk: Option<Arc<CompletionCacheDisk>>,  // optional second tier, see --completion-cache-disk-mb
 }
 
 impl CompletionCache {
     pub fn new(
     ) -> Self {
-        Self { map: HashMap::new(), in_added_order: Vec::new() }
+        Self { map: HashMap::new(), in_added_order: Vec::new(), disk: None }
     }
 }
 
@@ -55,37 +63,227 @@ pub fn cache_get(
     cache: Arc<StdRwLock<CompletionCache>>,
     key: (String, String),
 ) -> Option<serde_json::Value> {
-    let cache_locked = cache.write().unwrap();
+    let cache_locked = cache.read().unwrap();
     if let Some(value) = cache_locked.map.get(&key) {
         return Some(value.clone());
     }
-    None
+    _cache_get_typed_ahead(&cache_locked, &key)
 }
 
-pub fn cache_put(
+// Second tier, call it after cache_get() misses. The value has no snippet_telemetry_id, ids don't survive a restart,
+// register a new snippet and then put it back to memory with cache_put_memory(), so typing ahead works for it as well.
+pub async fn cache_get_disk(
     cache: Arc<StdRwLock<CompletionCache>>,
+    key: (String, String),
+    model: &str,
+) -> Option<serde_json::Value> {
+    let disk = cache.read().unwrap().disk.clone()?;
+    let model = model.to_string();
+    match tokio::task::spawn_blocking(move || disk.get(&model, &key)).await {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            warn!("{}", e);
+            None
+        }
+        Err(e) => {
+            warn!("completion cache get: {}", e);
+            None
+        }
+    }
+}
+
+// Model stopped because of max tokens, there is a continuation, so it's good for cache in the beginning, but don't believe it to the end.
+// For example CODECODECODECOMPLETION| with empty completion is obviously junk as cache.
+// And it's not junk for "stop", it actually saves one model call after accepting each completion.
+fn _believe_chars(code_completion: &str, finish_reason: &str) -> usize {
+    let chars = code_completion.chars().count();
+    if finish_reason == "length" {
+        chars.saturating_sub(10)
+    } else {
+        chars + 1
+    }
+}
+
+// The new key is the old one plus what the user typed, or, when both are cut to CACHE_KEY_CHARS, the same
+// window moved by the typed text
+fn _key_continues(old: &str, new: &str, typed: &str) -> bool {
+    let Some(new_before) = new.strip_suffix(typed) else {
+        return false;
+    };
+    if new_before == old {
+        return true;
+    }
+    old.ends_with(new_before) && new.chars().count() == CACHE_KEY_CHARS
+}
+
+// The user typed the beginning of a completion we already have, the rest of it goes out without calling the model.
+// Choices that disagree with what's typed are dropped, the most recent entry wins.
+fn _cache_get_typed_ahead(
+    cache_locked: &CompletionCache,
+    key: &(String, String),
+) -> Option<serde_json::Value> {
+    for old_key in cache_locked.in_added_order.iter().rev().take(CACHE_TYPED_AHEAD_ENTRIES) {
+        if old_key.1 != key.1 {
+            continue;
+        }
+        let Some(value) = cache_locked.map.get(old_key) else {
+            continue;
+        };
+        let choices = value.get("choices").and_then(|x| x.as_array()).cloned().unwrap_or_default();
+        let mut choices_ahead = vec![];
+        for choice in choices.iter() {
+            let code_completion = choice.get("code_completion").and_then(|x| x.as_str()).unwrap_or("");
+            let finish_reason = choice.get("finish_reason").and_then(|x| x.as_str()).unwrap_or("");
+            let believe_chars = _believe_chars(code_completion, finish_reason);
+            let typed_ahead = code_completion.char_indices().map(|(i, _)| i)
+                .chain(std::iter::once(code_completion.len()))
+                .take(believe_chars)
+                .skip(1)
+                .find(|i| _key_continues(&old_key.0, &key.0, &code_completion[..*i]));
+            if let Some(typed_len) = typed_ahead {
+                let mut choice_ahead = choice.clone();
+                choice_ahead["index"] = serde_json::json!(choices_ahead.len());
+                choice_ahead["code_completion"] = serde_json::json!(&code_completion[typed_len..]);
+                choices_ahead.push(choice_ahead);
+            }
+        }
+        if !choices_ahead.is_empty() {
+            let mut value_ahead = value.clone();
+            value_ahead["choices"] = serde_json::json!(choices_ahead);
+            return Some(value_ahead);
+        }
+    }
+    None
+}
+
+fn _cache_put_memory(
+    cache_locked: &mut CompletionCache,
     new_key: (String, String),
     value: serde_json::Value,
 ) {
-    let mut cache_locked = cache.write().unwrap();
     while cache_locked.in_added_order.len() > CACHE_ENTRIES {
         let old_key = cache_locked.in_added_order.remove(0);
         cache_locked.map.remove(&old_key);
     }
     // info!("cache put: {:?} = {:?}", new_key, value);
-    let mut new_key_copy = new_key.clone();
-    if new_key_copy.0.len() > CACHE_KEY_CHARS {
-        new_key_copy.0 = new_key_copy.0[..CACHE_KEY_CHARS].to_string();
+    cache_locked.map.entry(new_key.clone()).or_insert(value);
+    cache_locked.in_added_order.push(new_key);
+}
+
+fn _cache_key_cut(key: &(String, String)) -> (String, String) {
+    let mut key_copy = key.clone();
+    let key_chars = key_copy.0.chars().count();
+    if key_chars > CACHE_KEY_CHARS {
+        key_copy.0 = key_copy.0.chars().skip(key_chars - CACHE_KEY_CHARS).collect();
     }
-    cache_locked.map.entry(new_key_copy.clone()).or_insert(value);
-    cache_locked.in_added_order.push(new_key_copy.clone());
+    key_copy
+}
+
+// Memory only, for values that came from disk
+pub fn cache_put_memory(
+    cache: Arc<StdRwLock<CompletionCache>>,
+    new_key: (String, String),
+    value: serde_json::Value,
+) {
+    _cache_put_memory(&mut cache.write().unwrap(), _cache_key_cut(&new_key), value);
+}
+
+pub fn cache_put(
+    cache: Arc<StdRwLock<CompletionCache>>,
+    new_key: (String, String),
+    value: serde_json::Value,
+) {
+    let new_key_copy = _cache_key_cut(&new_key);
+    let disk = {
+        let mut cache_locked = cache.write().unwrap();
+        _cache_put_memory(&mut cache_locked, new_key_copy.clone(), value.clone());
+        cache_locked.disk.clone()
+    };
+    if let Some(disk) = disk {
+        let model = value.get("model").and_then(|x| x.as_str()).unwrap_or("").to_string();
+        // snippet ids start over after a restart, a stored one would point to somebody else's snippet
+        let mut value = value;
+        if let Some(map) = value.as_object_mut() {
+            map.remove("snippet_telemetry_id");
+        }
+        if let Some(choices) = value.get_mut("choices").and_then(|x| x.as_array_mut()) {
+            for choice in choices.iter_mut().filter_map(|x| x.as_object_mut()) {
+                choice.remove("snippet_telemetry_id");
+            }
+        }
+        let write = move || {
+            if let Err(e) = disk.put(&model, &new_key_copy, &value) {
+                warn!("{}", e);
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => { handle.spawn_blocking(write); }
+            Err(_) => write(),
+        }
+    }
+}
+
+async fn _disk_clear(
+    disk: Option<Arc<CompletionCacheDisk>>,
+    models: Vec<Option<String>>,
+) -> usize {
+    let Some(disk) = disk else {
+        return 0;
+    };
+    let cleared = tokio::task::spawn_blocking(move || {
+        let mut removed = 0;
+        for model in models.iter() {
+            match disk.clear(model.as_deref()) {
+                Ok(n) => removed += n,
+                Err(e) => warn!("{}", e),
+            }
+        }
+        removed
+    }).await;
+    cleared.unwrap_or_else(|e| {
+        warn!("completion cache clear: {}", e);
+        0
+    })
+}
+
+// Entries produced by models whose caps record changed, returns how many were removed
+pub async fn cache_invalidate_models(
+    cache: Arc<StdRwLock<CompletionCache>>,
+    models: &HashSet<String>,
+) -> usize {
+    let (removed, disk) = {
+        let mut cache_locked = cache.write().unwrap();
+        let before = cache_locked.map.len();
+        cache_locked.map.retain(|_, value| {
+            !models.contains(value.get("model").and_then(|x| x.as_str()).unwrap_or(""))
+        });
+        let cache_locked = &mut *cache_locked;
+        let map = &cache_locked.map;
+        cache_locked.in_added_order.retain(|key| map.contains_key(key));
+        (before - cache_locked.map.len(), cache_locked.disk.clone())
+    };
+    removed + _disk_clear(disk, models.iter().map(|x| Some(x.clone())).collect()).await
+}
+
+// Both tiers, returns how many entries were removed
+pub async fn cache_clear(
+    cache: Arc<StdRwLock<CompletionCache>>,
+) -> usize {
+    let (removed, disk) = {
+        let mut cache_locked = cache.write().unwrap();
+        let removed = cache_locked.map.len();
+        cache_locked.map.clear();
+        cache_locked.in_added_order.clear();
+        (removed, cache_locked.disk.clone())
+    };
+    removed + _disk_clear(disk, vec![None]).await
 }
 
 pub fn cache_key_from_post(
     post: &CodeCompletionPost,
 ) -> (String, String) {
-    // Change this function only together with the function below, it fills the cache ahead of cursor
-    // directly manupulating the cache key.
+    // Change this function only together with _key_continues(), it finds completions typed ahead
+    // by comparing the cache keys.
     let text_maybe = post.inputs.sources.get(&post.inputs.cursor.file);
     if let None = text_maybe {
         // Don't handle it there, validation should have caught it
@@ -122,10 +320,9 @@ pub fn cache_key_from_post(
     let mut key = "".to_string();
     key.push_str(&linesvec.join(""));
     key.push_str(&cursor_line.to_string());
-    let chars = key.chars();
-
-    if chars.clone().count() > CACHE_KEY_CHARS {
-        key = chars.skip(key.len() - CACHE_KEY_CHARS).collect();
+    let key_chars = key.chars().count();
+    if key_chars > CACHE_KEY_CHARS {
+        key = key.chars().skip(key_chars - CACHE_KEY_CHARS).collect();
     }
     return (key, cache_part2_from_post(post));
 }
@@ -142,33 +339,114 @@ impl Drop for CompletionSaveToCache {
         if self.completion0_finish_reason.is_empty() { // error happened
             return;
         }
-        let mut believe_chars = self.completion0_text.len();
-        if self.completion0_finish_reason == "length" {
-            // Model stopped because of max tokens, there is a continuation, so it's good for cache in the beginning, but don't believe it to the end.
-            // For example CODECODECODECOMPLETION| with empty completion is obviously junk as cache.
-            // And it's not junk for "stop", it actually saves one model call after accepting each completion.
-            believe_chars = believe_chars.checked_sub(10).unwrap_or(0);
-        } else {
-            believe_chars += 1;
-        }
-        for char_num in 0..believe_chars {
-            let code_completion_ahead: String = self.completion0_text.chars().skip(char_num).collect();
-            let cache_key_ahead: (String, String) = (
-                self.cache_key.0.clone() + &self.completion0_text.chars().take(char_num).collect::<String>(),
-                self.cache_key.1.clone()
-            );
-            cache_put(self.cache_arc.clone(), cache_key_ahead, serde_json::json!(
-                {
-                    "choices": [{
-                        "index": 0,
-                        "code_completion": code_completion_ahead,
-                        "finish_reason": self.completion0_finish_reason,
-                    }],
-                    "model": self.model,
-                    "cached": true,
-                    "snippet_telemetry_id": self.completion0_snippet_telemetry_id,
-                }
-            ));
+        if _believe_chars(&self.completion0_text, &self.completion0_finish_reason) == 0 {
+            return;
+        }
+        // one entry for all the candidates, cache_get() serves them ahead of cursor while the user types
+        let mut choices = vec![(self.completion0_text.clone(), self.completion0_finish_reason.clone(), self.completion0_snippet_telemetry_id)];
+        for (i, (code_completion, finish_reason)) in self.other_completions.iter().enumerate() {
+            choices.push((code_completion.clone(), finish_reason.clone(), self.other_snippet_telemetry_ids.get(i).cloned()));
+        }
+        let json_choices = choices.into_iter().enumerate().map(|(i, (code_completion, finish_reason, snippet_telemetry_id))| {
+            serde_json::json!({
+                "index": i,
+                "code_completion": code_completion,
+                "finish_reason": finish_reason,
+                "snippet_telemetry_id": snippet_telemetry_id,
+            })
+        }).collect::<Vec<_>>();
+        cache_put(self.cache_arc.clone(), self.cache_key.clone(), serde_json::json!(
+            {
+                "choices": json_choices,
+                "model": self.model,
+                "cached": true,
+                "snippet_telemetry_id": self.completion0_snippet_telemetry_id,
+            }
+        ));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn key(prefix: &str) -> (String, String) {
+        (prefix.to_string(), "multiline".to_string())
+    }
+
+    #[test]
+    fn test_typed_ahead() {
+        let cache = Arc::new(StdRwLock::new(CompletionCache::new()));
+        cache_put(cache.clone(), key("def f(a, b):\n    return "), json!({
+            "choices": [
+                {"index": 0, "code_completion": "a + b", "finish_reason": "stop", "snippet_telemetry_id": 7},
+                {"index": 1, "code_completion": "a - b", "finish_reason": "stop", "snippet_telemetry_id": 8},
+                {"index": 2, "code_completion": "b", "finish_reason": "stop", "snippet_telemetry_id": 9},
+            ],
+            "model": "m",
+            "cached": true,
+            "snippet_telemetry_id": 7,
+        }));
+        let hit = cache_get(cache.clone(), key("def f(a, b):\n    return a ")).unwrap();
+        assert_eq!(hit["choices"], json!([
+            {"index": 0, "code_completion": "+ b", "finish_reason": "stop", "snippet_telemetry_id": 7},
+            {"index": 1, "code_completion": "- b", "finish_reason": "stop", "snippet_telemetry_id": 8},
+        ]));
+        assert_eq!(hit["snippet_telemetry_id"], json!(7));
+        let hit = cache_get(cache.clone(), key("def f(a, b):\n    return a + b")).unwrap();
+        assert_eq!(hit["choices"], json!([{"index": 0, "code_completion": "", "finish_reason": "stop", "snippet_telemetry_id": 7}]));
+        assert!(cache_get(cache.clone(), key("def f(a, b):\n    return c")).is_none());
+        assert!(cache_get(cache.clone(), ("def f(a, b):\n    return a".to_string(), "singleline".to_string())).is_none());
+
+        // both keys are cut to CACHE_KEY_CHARS, the window moves as the user types
+        let long_prefix = "x".repeat(CACHE_KEY_CHARS - 2) + "= ";
+        cache_put(cache.clone(), key(&long_prefix), json!({
+            "choices": [{"index": 0, "code_completion": "compute_total(orders)", "finish_reason": "length"}],
+        }));
+        let typed = format!("{}compute", long_prefix);
+        let typed = typed.chars().skip(typed.len() - CACHE_KEY_CHARS).collect::<String>();
+        let hit = cache_get(cache.clone(), key(&typed)).unwrap();
+        assert_eq!(hit["choices"][0]["code_completion"], json!("_total(orders)"));
+        // cut by length, the end of it is not trusted
+        let typed = format!("{}compute_total(or", long_prefix);
+        let typed = typed.chars().skip(typed.len() - CACHE_KEY_CHARS).collect::<String>();
+        assert!(cache_get(cache.clone(), key(&typed)).is_none());
+
+        // only the latest entries are looked at
+        for i in 0..CACHE_TYPED_AHEAD_ENTRIES {
+            cache_put(cache.clone(), key(&format!("y{} = ", i)), json!({
+                "choices": [{"index": 0, "code_completion": "0", "finish_reason": "stop"}],
+            }));
+        }
+        assert!(cache_get(cache.clone(), key("def f(a, b):\n    return a ")).is_none());
+    }
+    #[test]
+    fn test_disk_tier() {
+        let path = std::env::temp_dir().join(format!("completion-cache-test-{}.sqlite", std::process::id()));
+        let disk = CompletionCacheDisk::open(&path, 1_000_000, std::time::Duration::from_secs(3600)).unwrap();
+        let cache = Arc::new(StdRwLock::new(CompletionCache::new()));
+        cache.write().unwrap().disk = Some(Arc::new(disk));
+        // no runtime, so it's written right away
+        cache_put(cache.clone(), key("x = "), json!({
+            "choices": [{"index": 0, "code_completion": "1", "finish_reason": "stop", "snippet_telemetry_id": 101}],
+            "model": "m",
+            "cached": true,
+            "snippet_telemetry_id": 101,
+        }));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let hit = runtime.block_on(cache_get_disk(cache.clone(), key("x = "), "m")).unwrap();
+        assert_eq!(hit["choices"][0]["code_completion"], json!("1"));
+        assert!(hit.get("snippet_telemetry_id").is_none());
+        assert!(hit["choices"][0].get("snippet_telemetry_id").is_none());
+        assert!(runtime.block_on(cache_get_disk(cache.clone(), key("x = "), "other-model")).is_none());
+        assert_eq!(runtime.block_on(cache_clear(cache.clone())), 2);
+        assert!(runtime.block_on(cache_get_disk(cache.clone(), key("x = "), "m")).is_none());
+        drop((runtime, cache));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
         }
     }
 }
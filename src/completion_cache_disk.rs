@@ -0,0 +1,206 @@
+use std::path::PathBuf;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime};
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::json;
+
+
+// Second tier of the completion cache, it survives a restart. Rows are partitioned by model, so one model
+// never serves completions of another one, and clearing a model doesn't touch the rest. Rows older than
+// ttl are dropped, and when the total size goes over max_bytes the least recently used rows go first.
+#[derive(Debug)]
+pub struct CompletionCacheDisk {
+    conn: StdMutex<Connection>,
+    pub max_bytes: usize,
+    pub ttl: Duration,
+}
+
+fn _now() -> i64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs() as i64
+}
+
+// Keys are up to CACHE_KEY_CHARS of code, the table only needs to match them exactly
+fn _key_hash(key: &(String, String)) -> String {
+    format!("{:x}", md5::compute(format!("{}\n{}", key.1, key.0)))
+}
+
+impl CompletionCacheDisk {
+    pub fn open(path: &PathBuf, max_bytes: usize, ttl: Duration) -> Result<CompletionCacheDisk, String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let conn = Connection::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        CompletionCacheDisk::_init(conn, max_bytes, ttl)
+    }
+
+    fn _init(conn: Connection, max_bytes: usize, ttl: Duration) -> Result<CompletionCacheDisk, String> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS completions (
+                model TEXT NOT NULL,
+                key_hash TEXT NOT NULL,
+                value TEXT NOT NULL,
+                bytes INTEGER NOT NULL,
+                time_added INTEGER NOT NULL,
+                time_last_used INTEGER NOT NULL,
+                PRIMARY KEY (model, key_hash)
+            );
+            CREATE INDEX IF NOT EXISTS idx_completions_last_used ON completions (time_last_used);"
+        ).map_err(|e| format!("completion cache init: {}", e))?;
+        Ok(CompletionCacheDisk { conn: StdMutex::new(conn), max_bytes, ttl })
+    }
+
+    fn _expired_before(&self) -> i64 {
+        _now() - self.ttl.as_secs() as i64
+    }
+
+    pub fn get(&self, model: &str, key: &(String, String)) -> Result<Option<serde_json::Value>, String> {
+        let conn = self.conn.lock().unwrap();
+        let key_hash = _key_hash(key);
+        let value: Option<String> = conn.query_row(
+            "SELECT value FROM completions WHERE model = ?1 AND key_hash = ?2 AND time_added > ?3",
+            params![model, key_hash, self._expired_before()],
+            |row| row.get(0),
+        ).optional().map_err(|e| format!("completion cache get: {}", e))?;
+        if value.is_none() {
+            return Ok(None);
+        }
+        conn.execute(
+            "UPDATE completions SET time_last_used = ?1 WHERE model = ?2 AND key_hash = ?3",
+            params![_now(), model, key_hash],
+        ).map_err(|e| format!("completion cache get: {}", e))?;
+        Ok(value.and_then(|x| serde_json::from_str(&x).ok()))
+    }
+
+    pub fn put(&self, model: &str, key: &(String, String), value: &serde_json::Value) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        let value_str = value.to_string();
+        let now = _now();
+        conn.execute(
+            "INSERT OR REPLACE INTO completions (model, key_hash, value, bytes, time_added, time_last_used) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![model, _key_hash(key), value_str, value_str.len() as i64, now, now],
+        ).map_err(|e| format!("completion cache put: {}", e))?;
+        self._evict(&conn).map_err(|e| format!("completion cache evict: {}", e))?;
+        Ok(())
+    }
+
+    fn _evict(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        let expired = conn.execute(
+            "DELETE FROM completions WHERE time_added <= ?1",
+            params![self._expired_before()],
+        )?;
+        let total: i64 = conn.query_row("SELECT COALESCE(SUM(bytes), 0) FROM completions", [], |row| row.get(0))?;
+        let mut excess = total - self.max_bytes as i64;
+        if excess <= 0 {
+            return Ok(expired);
+        }
+        let mut rowids = vec![];
+        {
+            let mut stmt = conn.prepare("SELECT rowid, bytes FROM completions ORDER BY time_last_used ASC, rowid ASC")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+            for row in rows {
+                if excess <= 0 {
+                    break;
+                }
+                let (rowid, bytes) = row?;
+                excess -= bytes;
+                rowids.push(rowid);
+            }
+        }
+        for rowid in rowids.iter() {
+            conn.execute("DELETE FROM completions WHERE rowid = ?1", params![rowid])?;
+        }
+        Ok(expired + rowids.len())
+    }
+
+    pub fn stats(&self) -> Result<serde_json::Value, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut models = serde_json::Map::new();
+        let (mut entries, mut bytes) = (0, 0);
+        let mut stmt = conn.prepare(
+            "SELECT model, COUNT(*), SUM(bytes), MIN(time_added), MAX(time_last_used) FROM completions WHERE time_added > ?1 GROUP BY model"
+        ).map_err(|e| format!("completion cache stats: {}", e))?;
+        let rows = stmt.query_map(params![self._expired_before()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?, row.get::<_, i64>(4)?))
+        }).map_err(|e| format!("completion cache stats: {}", e))?;
+        for row in rows {
+            let (model, model_entries, model_bytes, oldest, last_used) = row.map_err(|e| format!("completion cache stats: {}", e))?;
+            entries += model_entries;
+            bytes += model_bytes;
+            models.insert(model, json!({
+                "entries": model_entries,
+                "bytes": model_bytes,
+                "oldest_ts": oldest,
+                "last_used_ts": last_used,
+            }));
+        }
+        Ok(json!({
+            "entries": entries,
+            "bytes": bytes,
+            "max_bytes": self.max_bytes,
+            "ttl_seconds": self.ttl.as_secs(),
+            "models": models,
+        }))
+    }
+
+    // All models if None, returns how many rows were removed
+    pub fn clear(&self, model: Option<&str>) -> Result<usize, String> {
+        let conn = self.conn.lock().unwrap();
+        match model {
+            Some(model) => conn.execute("DELETE FROM completions WHERE model = ?1", params![model]),
+            None => conn.execute("DELETE FROM completions", []),
+        }.map_err(|e| format!("completion cache clear: {}", e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(prefix: &str) -> (String, String) {
+        (prefix.to_string(), "multiline".to_string())
+    }
+
+    fn value(code_completion: &str) -> serde_json::Value {
+        json!({"choices": [{"index": 0, "code_completion": code_completion, "finish_reason": "stop"}], "cached": true})
+    }
+
+    #[test]
+    fn test_partitions_and_clear() {
+        let disk = CompletionCacheDisk::_init(Connection::open_in_memory().unwrap(), 1_000_000, Duration::from_secs(3600)).unwrap();
+        disk.put("model-a", &key("def f("), &value("a, b):")).unwrap();
+        disk.put("model-b", &key("def f("), &value("x):")).unwrap();
+        assert_eq!(disk.get("model-a", &key("def f(")).unwrap(), Some(value("a, b):")));
+        assert_eq!(disk.get("model-b", &key("def f(")).unwrap(), Some(value("x):")));
+        assert_eq!(disk.get("model-c", &key("def f(")).unwrap(), None);
+        assert_eq!(disk.get("model-a", &("def f(".to_string(), "singleline".to_string())).unwrap(), None);
+
+        let stats = disk.stats().unwrap();
+        assert_eq!(stats["entries"], json!(2));
+        assert_eq!(stats["models"]["model-a"]["entries"], json!(1));
+
+        assert_eq!(disk.clear(Some("model-a")).unwrap(), 1);
+        assert_eq!(disk.get("model-a", &key("def f(")).unwrap(), None);
+        assert_eq!(disk.get("model-b", &key("def f(")).unwrap(), Some(value("x):")));
+        assert_eq!(disk.clear(None).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let row_bytes = value("completion 0").to_string().len();
+        let disk = CompletionCacheDisk::_init(Connection::open_in_memory().unwrap(), 3 * row_bytes, Duration::from_secs(3600)).unwrap();
+        for i in 0..5 {
+            disk.put("m", &key(&format!("prefix {}", i)), &value(&format!("completion {}", i))).unwrap();
+        }
+        assert_eq!(disk.stats().unwrap()["entries"], json!(3));
+        assert_eq!(disk.get("m", &key("prefix 1")).unwrap(), None);
+        assert_eq!(disk.get("m", &key("prefix 4")).unwrap(), Some(value("completion 4")));
+
+        // everything is expired right away
+        let disk = CompletionCacheDisk::_init(Connection::open_in_memory().unwrap(), 3 * row_bytes, Duration::ZERO).unwrap();
+        disk.put("m", &key("prefix"), &value("completion")).unwrap();
+        assert_eq!(disk.get("m", &key("prefix")).unwrap(), None);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::sync::RwLock as StdRwLock;
+
+use tokio::sync::Notify;
+
+use crate::telemetry::telemetry_structs;
+
+
+// Completions waiting for the model, one per client and document. When the user types fast, a new request
+// for the same document makes the previous one stop waiting, and the upstream request is dropped with it.
+#[derive(Debug, Default)]
+pub struct CompletionsInFlight {
+    next_request_id: u64,
+    running: HashMap<(String, String), (u64, Arc<Notify>)>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Running,
+    Finished,
+    Superseded,
+}
+
+// Lives as long as the request. If it's dropped while still running, the client went away: HTTP connection
+// closed, or LSP $/cancelRequest (tower-lsp drops the handler future). Either way it's counted in telemetry.
+pub struct InFlightGuard {
+    in_flight: Arc<StdMutex<CompletionsInFlight>>,
+    tele_storage: Arc<StdRwLock<telemetry_structs::Storage>>,
+    key: (String, String),
+    pub request_id: u64,
+    superseded: Arc<Notify>,
+    outcome: Outcome,
+}
+
+pub fn completion_started(
+    in_flight: Arc<StdMutex<CompletionsInFlight>>,
+    tele_storage: Arc<StdRwLock<telemetry_structs::Storage>>,
+    client_id: &str,
+    file: &str,
+) -> InFlightGuard {
+    let key = (client_id.to_string(), file.to_string());
+    let superseded = Arc::new(Notify::new());
+    let request_id = {
+        let mut in_flight_locked = in_flight.lock().unwrap();
+        in_flight_locked.next_request_id += 1;
+        let request_id = in_flight_locked.next_request_id;
+        // without client_id, requests can't be told apart from requests of other clients, they never supersede
+        if !client_id.is_empty() {
+            if let Some((_, previous)) = in_flight_locked.running.insert(key.clone(), (request_id, superseded.clone())) {
+                // notify_one() keeps the permit if the previous request isn't waiting yet
+                previous.notify_one();
+            }
+        }
+        request_id
+    };
+    InFlightGuard {
+        in_flight,
+        tele_storage,
+        key,
+        request_id,
+        superseded,
+        outcome: Outcome::Running,
+    }
+}
+
+impl InFlightGuard {
+    // None if a newer request for the same document came first. A stream calls it for each piece it waits for.
+    pub async fn run_unless_superseded<T>(&mut self, f: impl Future<Output = T>) -> Option<T> {
+        let superseded = self.superseded.clone();
+        tokio::select! {
+            x = f => Some(x),
+            _ = superseded.notified() => {
+                self.outcome = Outcome::Superseded;
+                None
+            }
+        }
+    }
+
+    // The answer (or an error) is out, dropping the guard after that is not a cancellation
+    pub fn finish(&mut self) {
+        if self.outcome == Outcome::Running {
+            self.outcome = Outcome::Finished;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        {
+            let mut in_flight_locked = self.in_flight.lock().unwrap();
+            if in_flight_locked.running.get(&self.key).map(|(id, _)| *id == self.request_id).unwrap_or(false) {
+                in_flight_locked.running.remove(&self.key);
+            }
+        }
+        let reason = match self.outcome {
+            Outcome::Finished => return,
+            Outcome::Superseded => "superseded",
+            Outcome::Running => "cancelled by client",
+        };
+        self.tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
+            "".to_string(),
+            "completion-cancelled".to_string(),
+            false,
+            reason.to_string(),
+        ));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_superseded() {
+        let in_flight = Arc::new(StdMutex::new(CompletionsInFlight::default()));
+        let tele_storage = Arc::new(StdRwLock::new(telemetry_structs::Storage::new()));
+        let mut first = completion_started(in_flight.clone(), tele_storage.clone(), "lsp-0", "file:///a.py");
+        let mut other_file = completion_started(in_flight.clone(), tele_storage.clone(), "lsp-0", "file:///b.py");
+        let mut second = completion_started(in_flight.clone(), tele_storage.clone(), "lsp-0", "file:///a.py");
+        assert!(second.request_id > first.request_id);
+
+        assert_eq!(first.run_unless_superseded(std::future::pending::<()>()).await, None);
+        first.finish();
+        assert_eq!(second.run_unless_superseded(async { 2 }).await, Some(2));
+        assert_eq!(second.run_unless_superseded(async { 22 }).await, Some(22));
+        second.finish();
+        assert_eq!(other_file.run_unless_superseded(async { 3 }).await, Some(3));
+        other_file.finish();
+        let dropped = completion_started(in_flight.clone(), tele_storage.clone(), "lsp-1", "file:///a.py");
+        drop((first, second, other_file, dropped));
+
+        // no client_id, nothing to supersede
+        let mut anonymous1 = completion_started(in_flight.clone(), tele_storage.clone(), "", "file:///a.py");
+        let mut anonymous2 = completion_started(in_flight.clone(), tele_storage.clone(), "", "file:///a.py");
+        assert_eq!(anonymous1.run_unless_superseded(async { 1 }).await, Some(1));
+        assert_eq!(anonymous2.run_unless_superseded(async { 2 }).await, Some(2));
+        anonymous1.finish();
+        anonymous2.finish();
+        drop((anonymous1, anonymous2));
+
+        assert!(in_flight.lock().unwrap().running.is_empty());
+        let reasons = tele_storage.read().unwrap().tele_net.iter().map(|x| x.error_message.clone()).collect::<Vec<_>>();
+        assert_eq!(reasons, vec!["superseded", "cancelled by client"]);
+    }
+}
@@ -77,7 +77,7 @@ pub async fn files_in_jsonl(global_context: Arc<ARwLock<GlobalContext>>) -> Vec<
     }
 }
 
-fn make_async_watcher() -> notify::Result<(RecommendedWatcher, Receiver<notify::Result<Event>>)> {
+pub(crate) fn make_async_watcher() -> notify::Result<(RecommendedWatcher, Receiver<notify::Result<Event>>)> {
     let (mut tx, rx) = channel(1);
 
     let watcher = RecommendedWatcher::new(
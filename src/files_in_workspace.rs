@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::hash::Hash;
 use std::io;
 use std::path::PathBuf;
@@ -7,16 +8,23 @@ use std::time::Instant;
 use ropey::Rope;
 use tokio::fs::read_to_string;
 use tokio::sync::RwLock as ARwLock;
-use tracing::info;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
+use futures::StreamExt;
+use notify::{EventKind, RecursiveMode, Watcher};
+use notify::event::ModifyKind;
+use tracing::{error, info};
 use url::Url;
 
 use crate::global_context;
 use crate::global_context::GlobalContext;
+use crate::files_in_jsonl::make_async_watcher;
 use crate::telemetry;
 use walkdir::WalkDir;
 use which::which;
 use crate::vecdb::file_filter::is_valid_file;
 
+const WATCHED_FOLDERS_RECHECK: std::time::Duration = std::time::Duration::from_secs(5);
+
 #[derive(Debug, Eq, Hash, PartialEq, Clone)]
 pub struct Document {
     #[allow(dead_code)]
@@ -210,7 +218,7 @@ pub async fn enqueue_all_files_from_workspace_folders(
 pub async fn on_workspaces_init(
     gcx: Arc<ARwLock<global_context::GlobalContext>>,
 ) -> i32 {
-    // TODO: this will not work when files change. Need a real file watcher.
+    // changes after this point are picked up by file_watcher_background_task
     enqueue_all_files_from_workspace_folders(gcx.clone()).await
 }
 
@@ -226,6 +234,7 @@ pub async fn on_did_open(
     let doc = Document::new(language_id.clone(), Rope::from_str(&text));
     let doc_info = DocumentInfo { uri: file_url.clone(), document: Some(doc.clone()) };
     document_map_locked.insert(file_url.clone(), doc);
+    gcx_locked.documents_state.document_touched.lock().unwrap().insert(file_url.clone(), Instant::now());
     let path_str = format!("{:?}", doc_info.get_path());
     let last_30_chars: String = crate::nicer_logs::last_n_chars(&path_str, 30);
     info!("opened {}", last_30_chars);
@@ -245,8 +254,80 @@ pub async fn on_did_change(
         let doc = document_map_locked.entry(file_url.clone())
             .or_insert(Document::new("unknown".to_owned(), Rope::new()));
         doc.text = Rope::from_str(&text);
+        gcx_locked.documents_state.document_touched.lock().unwrap().insert(file_url.clone(), t0);
         DocumentInfo { uri: file_url.clone(), document: Some(doc.clone()) }
     };
+    _on_document_changed(gcx, doc_info, text, t0).await;
+}
+
+pub async fn on_did_change_incremental(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    file_url: &Url,
+    changes: &Vec<TextDocumentContentChangeEvent>,
+) {
+    let t0 = Instant::now();
+    // a full text change makes the changes before it pointless
+    let last_full = changes.iter().rposition(|change| change.range.is_none());
+    let document_map = gcx.read().await.documents_state.document_map.clone();
+    let is_open = document_map.read().await.contains_key(file_url);
+    // never opened, so the edits go to the text on disk, that's what the client most likely had before them
+    let mut text_from_disk = None;
+    if !is_open && last_full.is_none() {
+        match (DocumentInfo { uri: file_url.clone(), document: None }).read_file().await {
+            Ok(text) => text_from_disk = Some(Rope::from_str(&text)),
+            Err(e) => {
+                error!("change of {} that was never opened, can't read it: {}", file_url, e);
+                return;
+            }
+        }
+    }
+    let (doc_info, text) = {
+        let gcx_locked = gcx.read().await;
+        let mut document_map_locked = document_map.write().await;
+        let doc = document_map_locked.entry(file_url.clone())
+            .or_insert(Document::new("unknown".to_owned(), text_from_disk.unwrap_or_default()));
+        for change in &changes[last_full.unwrap_or(0)..] {
+            match change.range {
+                Some(range) => apply_range_edit(&mut doc.text, &range, &change.text),
+                None => doc.text = Rope::from_str(&change.text),
+            }
+        }
+        gcx_locked.documents_state.document_touched.lock().unwrap().insert(file_url.clone(), t0);
+        (DocumentInfo { uri: file_url.clone(), document: Some(doc.clone()) }, doc.text.to_string())
+    };
+    _on_document_changed(gcx, doc_info, &text, t0).await;
+}
+
+// LSP positions count utf-16 code units, positions past the end of a line or the document are clamped
+pub fn lsp_position_to_char(rope: &Rope, position: &Position) -> usize {
+    let line = position.line as usize;
+    if line >= rope.len_lines() {
+        return rope.len_chars();
+    }
+    let line_slice = rope.line(line);
+    let mut line_len = line_slice.len_chars();
+    while line_len > 0 && matches!(line_slice.char(line_len - 1), '\n' | '\r') {
+        line_len -= 1;
+    }
+    let line_start = rope.line_to_char(line);
+    let line_end = line_start + line_len;
+    let cu = rope.char_to_utf16_cu(line_start) + position.character as usize;
+    rope.utf16_cu_to_char(cu.min(rope.char_to_utf16_cu(line_end))).min(line_end)
+}
+
+fn apply_range_edit(rope: &mut Rope, range: &tower_lsp::lsp_types::Range, text: &str) {
+    let start = lsp_position_to_char(rope, &range.start);
+    let end = lsp_position_to_char(rope, &range.end).max(start);
+    rope.remove(start..end);
+    rope.insert(start, text);
+}
+
+async fn _on_document_changed(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    doc_info: DocumentInfo,
+    text: &String,
+    t0: Instant,
+) {
     if is_valid_file(&doc_info.get_path()) {
         {
             let vecdb_bind = gcx.read().await.vec_db.clone();
@@ -272,3 +353,239 @@ pub async fn on_did_change(
     let last_30_chars: String = crate::nicer_logs::last_n_chars(&doc_info.get_path().display().to_string(), 30);
     info!("changed {}, total time {:.3}s", last_30_chars, t0.elapsed().as_secs_f32());
 }
+
+pub async fn on_did_create_or_modify_files(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    paths: &Vec<PathBuf>,
+) {
+    let docs = _files_in_dirs(paths).iter()
+        .filter(|p| is_valid_file(p))
+        .filter_map(|p| DocumentInfo::from_pathbuf(p).ok())
+        .collect::<Vec<_>>();
+    if docs.is_empty() {
+        return;
+    }
+    let (ast_module, vecdb_module) = {
+        let cx_locked = gcx.read().await;
+        let mut workspace_files = cx_locked.documents_state.workspace_files.lock().unwrap();
+        for doc in docs.iter() {
+            if !workspace_files.contains(&doc.uri) {
+                workspace_files.push(doc.uri.clone());
+            }
+        }
+        (cx_locked.ast_module.clone(), cx_locked.vec_db.clone())
+    };
+    match *ast_module.lock().await {
+        Some(ref mut ast) => ast.ast_indexer_enqueue_files(&docs, false).await,
+        None => {}
+    };
+    match *vecdb_module.lock().await {
+        Some(ref mut db) => db.vectorizer_enqueue_files(&docs, false).await,
+        None => {}
+    };
+}
+
+pub async fn on_did_delete_files(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    paths: &Vec<PathBuf>,
+) {
+    let (docs, ast_module, vecdb_module) = {
+        let cx_locked = gcx.read().await;
+        let mut workspace_files = cx_locked.documents_state.workspace_files.lock().unwrap();
+        let docs = _docs_under(&workspace_files, paths);
+        let removed_uris = docs.iter().map(|d| d.uri.clone()).collect::<HashSet<_>>();
+        workspace_files.retain(|uri| !removed_uris.contains(uri));
+        (docs, cx_locked.ast_module.clone(), cx_locked.vec_db.clone())
+    };
+    match *ast_module.lock().await {
+        Some(ref ast) => {
+            for doc in docs.iter() {
+                ast.remove_file(doc).await;
+            }
+        }
+        None => {}
+    };
+    match *vecdb_module.lock().await {
+        Some(ref db) => {
+            for doc in docs.iter() {
+                db.remove_file(&doc.get_path()).await;
+            }
+        }
+        None => {}
+    };
+    for doc in docs.iter() {
+        let last_30_chars: String = crate::nicer_logs::last_n_chars(&doc.get_path().display().to_string(), 30);
+        info!("deleted {}", last_30_chars);
+    }
+}
+
+// A directory created or moved into the workspace comes as one path, its files are walked here.
+// Hidden directories inside it are skipped, like .git of a repository moved in.
+fn _files_in_dirs(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        files.extend(WalkDir::new(path)
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.file_type().is_dir())
+            .map(|e| e.path().to_path_buf()));
+    }
+    files
+}
+
+// A removed directory is gone from disk, so it can't be walked: everything known under that path goes with it
+fn _docs_under(workspace_files: &Vec<Url>, paths: &Vec<PathBuf>) -> Vec<DocumentInfo> {
+    let mut docs = paths.iter()
+        .filter_map(|p| DocumentInfo::from_pathbuf(p).ok())
+        .collect::<Vec<_>>();
+    let mut seen = docs.iter().map(|d| d.uri.clone()).collect::<HashSet<_>>();
+    for uri in workspace_files {
+        let path = PathBuf::from(uri.path());
+        if paths.iter().any(|p| path.starts_with(p)) && seen.insert(uri.clone()) {
+            docs.push(DocumentInfo { uri: uri.clone(), document: None });
+        }
+    }
+    docs
+}
+
+// Only below the workspace root, the workspace itself can be anywhere, ~/.local/src/project for example
+fn _is_in_hidden_dir(path: &PathBuf, workspace_folders: &Vec<PathBuf>) -> bool {
+    let relative = workspace_folders.iter()
+        .filter_map(|folder| path.strip_prefix(folder).ok())
+        .min_by_key(|x| x.components().count())
+        .unwrap_or(path.as_path());
+    relative.components().any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+pub async fn file_watcher_background_task(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+) {
+    let (mut watcher, mut rx) = match make_async_watcher() {
+        Ok(x) => x,
+        Err(e) => {
+            error!("failed to make workspace file watcher: {}", e);
+            return;
+        }
+    };
+    let mut watched: Vec<PathBuf> = vec![];
+    loop {
+        // workspace folders arrive with LSP initialize, so keep the watch list in sync
+        let folders: Vec<PathBuf> = {
+            let cx_locked = gcx.read().await;
+            let x = cx_locked.documents_state.workspace_folders.lock().unwrap().clone();
+            x
+        };
+        for folder in folders.iter().filter(|f| !watched.contains(f)) {
+            match watcher.watch(folder, RecursiveMode::Recursive) {
+                Ok(_) => {
+                    info!("file watcher started watching {:?}", folder);
+                    watched.push(folder.clone());
+                }
+                Err(e) => error!("file watcher {:?} failed to start watching: {}", folder, e),
+            }
+        }
+        for folder in watched.iter().filter(|f| !folders.contains(f)) {
+            let _ = watcher.unwatch(folder);
+            info!("file watcher stopped watching {:?}", folder);
+        }
+        watched.retain(|f| folders.contains(f));
+
+        let event = match tokio::time::timeout(WATCHED_FOLDERS_RECHECK, rx.next()).await {
+            Ok(Some(Ok(event))) => event,
+            Ok(Some(Err(e))) => {
+                info!("file watch error: {:?}", e);
+                continue;
+            }
+            Ok(None) => break,
+            Err(_) => continue,
+        };
+        match event.kind {
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {}
+            _ => continue,
+        }
+        // a directory that appeared is walked for its files, other changes to a directory (metadata) don't matter
+        let dirs_appeared = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)));
+        // renames come as Modify(Name(..)) with old and/or new paths, existence tells which is which
+        let (existing, removed): (Vec<PathBuf>, Vec<PathBuf>) = event.paths.into_iter()
+            .filter(|p| !_is_in_hidden_dir(p, &folders))
+            .partition(|p| p.exists());
+        let existing = existing.into_iter().filter(|p| dirs_appeared || !p.is_dir()).collect::<Vec<_>>();
+        if !removed.is_empty() {
+            on_did_delete_files(gcx.clone(), &removed).await;
+        }
+        if !existing.is_empty() {
+            on_did_create_or_modify_files(gcx.clone(), &existing).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(text: &str, start: (u32, u32), end: (u32, u32), new_text: &str) -> String {
+        let mut rope = Rope::from_str(text);
+        let range = tower_lsp::lsp_types::Range {
+            start: Position { line: start.0, character: start.1 },
+            end: Position { line: end.0, character: end.1 },
+        };
+        apply_range_edit(&mut rope, &range, new_text);
+        rope.to_string()
+    }
+
+    #[test]
+    fn test_apply_range_edit() {
+        assert_eq!(edit("def f():\n    pass\n", (1, 4), (1, 8), "return 1"), "def f():\n    return 1\n");
+        assert_eq!(edit("abc\ndef\n", (0, 3), (1, 0), ""), "abcdef\n");
+        assert_eq!(edit("abc\n", (1, 0), (1, 0), "xyz"), "abc\nxyz");
+        // character past the end of the line is clamped to the line end
+        assert_eq!(edit("abc\ndef\n", (0, 100), (0, 100), "!"), "abc!\ndef\n");
+        // "😀" is two utf-16 code units
+        assert_eq!(edit("a😀b\n", (0, 3), (0, 4), "c"), "a😀c\n");
+    }
+
+    #[test]
+    fn test_is_in_hidden_dir() {
+        let folders = vec![PathBuf::from("/home/user/.local/src/project")];
+        assert!(!_is_in_hidden_dir(&PathBuf::from("/home/user/.local/src/project/src/main.rs"), &folders));
+        assert!(_is_in_hidden_dir(&PathBuf::from("/home/user/.local/src/project/.git/index"), &folders));
+        assert!(_is_in_hidden_dir(&PathBuf::from("/home/user/.local/src/project/src/.cache/x.rs"), &folders));
+        assert!(_is_in_hidden_dir(&PathBuf::from("/home/user/.local/other/main.rs"), &folders));
+    }
+
+    #[test]
+    fn test_files_in_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let moved_in = tmp.path().join("moved_in");
+        for f in ["a.py", "sub/b.rs", ".git/config.py"] {
+            let path = moved_in.join(f);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "x = 1\n").unwrap();
+        }
+        let single = tmp.path().join("single.py");
+        std::fs::write(&single, "y = 2\n").unwrap();
+        let mut files = _files_in_dirs(&vec![moved_in.clone(), single.clone()]);
+        files.sort();
+        assert_eq!(files, vec![moved_in.join("a.py"), moved_in.join("sub/b.rs"), single]);
+    }
+
+    #[test]
+    fn test_docs_under() {
+        let workspace_files = ["/p/src/a.py", "/p/src/sub/b.py", "/p/srcx/c.py", "/p/d.py"].iter()
+            .map(|p| Url::from_file_path(p).unwrap())
+            .collect::<Vec<_>>();
+        let docs = _docs_under(&workspace_files, &vec![PathBuf::from("/p/src"), PathBuf::from("/p/d.py")]);
+        let paths = docs.iter().map(|d| d.get_path()).collect::<Vec<_>>();
+        assert_eq!(paths, vec![
+            PathBuf::from("/p/src"),
+            PathBuf::from("/p/d.py"),
+            PathBuf::from("/p/src/a.py"),
+            PathBuf::from("/p/src/sub/b.py"),
+        ]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::pin::Pin;
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use reqwest::header::AUTHORIZATION;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderValue;
+
+// Shared by the endpoint styles that answer with one json per line: ollama and llama.cpp server
+
+pub type NdjsonStream = Pin<Box<dyn Stream<Item = Result<String, String>> + Send>>;
+
+
+fn _make_headers(bearer: &String) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json").unwrap());
+    if !bearer.is_empty() {
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(format!("Bearer {}", bearer).as_str()).unwrap());
+    }
+    headers
+}
+
+// One json per line. llama.cpp server prefixes the lines with "data: ", SSE style, strip that too.
+pub fn ndjson_stream(resp: reqwest::Response) -> NdjsonStream {
+    let mut bytes = resp.bytes_stream();
+    Box::pin(stream! {
+        let mut buf: Vec<u8> = vec![];
+        while let Some(chunk) = bytes.next().await {
+            match chunk {
+                Ok(chunk) => buf.extend_from_slice(&chunk),
+                Err(e) => {
+                    yield Err(format!("{}", e));
+                    return;
+                }
+            }
+            while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                if let Some(line) = _ndjson_line(&line) {
+                    yield Ok(line);
+                }
+            }
+        }
+        if let Some(line) = _ndjson_line(&buf) {
+            yield Ok(line);
+        }
+    })
+}
+
+fn _ndjson_line(line: &[u8]) -> Option<String> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    let line = line.strip_prefix("data:").unwrap_or(line).trim();
+    if line.is_empty() {
+        None
+    } else {
+        Some(line.to_string())
+    }
+}
+
+pub async fn post_and_check_status(
+    url: &String,
+    bearer: &String,
+    client: &reqwest::Client,
+    data: &serde_json::Value,
+) -> Result<reqwest::Response, String> {
+    if data["prompt"].as_str().unwrap_or("").starts_with("PASSTHROUGH ") {
+        return Err(format!("{}: chat passthrough is not supported by this endpoint style, use a chat scratchpad", url));
+    }
+    let resp = client.post(url)
+        .headers(_make_headers(bearer))
+        .body(data.to_string())
+        .send()
+        .await
+        .map_err(|e| format!("{}", e))?;
+    let status_code = resp.status().as_u16();
+    if status_code != 200 {
+        let response_txt = resp.text().await.map_err(|e|
+            format!("reading from socket {}: {}", url, e)
+        )?;
+        return Err(format!("{} status={} text {}", url, status_code, response_txt));
+    }
+    Ok(resp)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ndjson_line() {
+        assert_eq!(_ndjson_line(b"{\"response\": \"x\"}\n"), Some("{\"response\": \"x\"}".to_string()));
+        assert_eq!(_ndjson_line(b"data: {\"content\": \"x\"}\r\n"), Some("{\"content\": \"x\"}".to_string()));
+        assert_eq!(_ndjson_line(b"  \n"), None);
+    }
+}
@@ -0,0 +1,302 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest_eventsource::EventSource;
+use serde_json::json;
+use tracing::info;
+
+use crate::call_validation;
+use crate::call_validation::SamplingParameters;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+
+fn _make_headers(bearer: &String) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json").unwrap());
+    headers.insert(HeaderName::from_static("anthropic-version"), HeaderValue::from_static(ANTHROPIC_VERSION));
+    if !bearer.is_empty() {
+        headers.insert(HeaderName::from_static("x-api-key"), HeaderValue::from_str(bearer.as_str()).unwrap());
+    }
+    headers
+}
+
+fn _make_url(
+    model_name: &str,
+    prompt: &str,
+    endpoint_template: &String,
+    endpoint_chat_passthrough: &String,
+) -> String {
+    if prompt.starts_with("PASSTHROUGH ") && !endpoint_chat_passthrough.is_empty() {
+        endpoint_chat_passthrough.clone()
+    } else {
+        endpoint_template.replace("$MODEL", model_name)
+    }
+}
+
+fn _make_data(
+    model_name: &str,
+    prompt: &str,
+    sampling_parameters: &SamplingParameters,
+    stream: bool,
+) -> Result<serde_json::Value, String> {
+    let messages: Vec<call_validation::ChatMessage> = if prompt.starts_with("PASSTHROUGH ") {
+        serde_json::from_str(&prompt[12..]).map_err(|e| format!("passthrough messages: {}", e))?
+    } else {
+        vec![call_validation::ChatMessage {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        }]
+    };
+    let (system, messages) = _messages_to_content_blocks(&messages);
+    let mut data = json!({
+        "model": model_name,
+        "stream": stream,
+        "max_tokens": sampling_parameters.max_new_tokens,
+        "messages": messages,
+    });
+    if let Some(temperature) = sampling_parameters.temperature {
+        data["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = sampling_parameters.top_p {
+        data["top_p"] = json!(top_p);
+    }
+    if !system.is_empty() {
+        data["system"] = json!(system);
+    }
+    // the API rejects whitespace-only stop sequences, typical for code completion ("\n\n")
+    let stop_sequences = sampling_parameters.stop.clone().unwrap_or_default().into_iter()
+        .filter(|s| !s.trim().is_empty())
+        .collect::<Vec<_>>();
+    if !stop_sequences.is_empty() {
+        data["stop_sequences"] = json!(stop_sequences);
+    }
+    Ok(data)
+}
+
+// System messages go into a separate field. Everything that is not an assistant message is a user message
+// (context files, tool outputs), and consecutive messages of the same role are merged because the API
+// wants them to alternate, starting with the user.
+fn _messages_to_content_blocks(
+    messages: &Vec<call_validation::ChatMessage>,
+) -> (String, Vec<serde_json::Value>) {
+    let mut system: Vec<String> = vec![];
+    let mut result: Vec<serde_json::Value> = vec![];
+    for msg in messages {
+        if msg.role == "system" {
+            system.push(msg.content.clone());
+            continue;
+        }
+        if msg.content.is_empty() {
+            continue;
+        }
+        let role = if msg.role == "assistant" { "assistant" } else { "user" };
+        let block = json!({"type": "text", "text": msg.content});
+        let same_role = result.last().map(|last| last["role"] == role).unwrap_or(false);
+        if same_role {
+            result.last_mut().unwrap()["content"].as_array_mut().unwrap().push(block);
+        } else {
+            if result.is_empty() && role == "assistant" {
+                result.push(json!({"role": "user", "content": [{"type": "text", "text": "..."}]}));
+            }
+            result.push(json!({"role": role, "content": [block]}));
+        }
+    }
+    (system.join("\n\n"), result)
+}
+
+pub async fn forward_to_anthropic_style_endpoint(
+    save_url: &mut String,
+    bearer: String,
+    model_name: &str,
+    prompt: &str,
+    client: &reqwest::Client,
+    endpoint_template: &String,
+    endpoint_chat_passthrough: &String,
+    sampling_parameters: &SamplingParameters,
+) -> Result<serde_json::Value, String> {
+    let url = _make_url(model_name, prompt, endpoint_template, endpoint_chat_passthrough);
+    save_url.clone_from(&&url);
+    let data = _make_data(model_name, prompt, sampling_parameters, false)?;
+    let req = client.post(&url)
+        .headers(_make_headers(&bearer))
+        .body(data.to_string())
+        .send()
+        .await;
+    let resp = req.map_err(|e| format!("{}", e))?;
+    let status_code = resp.status().as_u16();
+    let response_txt = resp.text().await.map_err(|e|
+        format!("reading from socket {}: {}", url, e)
+    )?;
+    // errors come as {"type": "error", "error": {...}}, the level above reports them
+    if status_code != 200 && status_code != 400 && status_code != 404 {
+        return Err(format!("{} status={} text {}", url, status_code, response_txt));
+    }
+    if status_code != 200 {
+        info!("forward_to_anthropic_style_endpoint: {} {}\n{}", url, status_code, response_txt);
+    }
+    serde_json::from_str(&response_txt).map_err(|e| format!("{}: {}", url, e))
+}
+
+pub async fn forward_to_anthropic_style_endpoint_streaming(
+    save_url: &mut String,
+    bearer: String,
+    model_name: &str,
+    prompt: &str,
+    client: &reqwest::Client,
+    endpoint_template: &String,
+    endpoint_chat_passthrough: &String,
+    sampling_parameters: &SamplingParameters,
+) -> Result<EventSource, String> {
+    let url = _make_url(model_name, prompt, endpoint_template, endpoint_chat_passthrough);
+    save_url.clone_from(&&url);
+    let data = _make_data(model_name, prompt, sampling_parameters, true)?;
+    let builder = client.post(&url)
+        .headers(_make_headers(&bearer))
+        .body(data.to_string());
+    let event_source: EventSource = EventSource::new(builder).map_err(|e|
+        format!("can't stream from {}: {}", url, e)
+    )?;
+    Ok(event_source)
+}
+
+// Text of all content blocks and whether the model stopped by itself (not by max_tokens)
+pub fn anthropic_response_text(model_says: &serde_json::Value) -> Option<(String, bool)> {
+    if model_says.get("type")?.as_str()? != "message" {
+        return None;
+    }
+    let text = model_says.get("content")?.as_array()?.iter()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+        .map(|block| block.get("text").and_then(|t| t.as_str()).unwrap_or("").to_string())
+        .collect::<Vec<_>>()
+        .join("");
+    let stop_reason = model_says.get("stop_reason").and_then(|x| x.as_str()).unwrap_or("");
+    Some((text, anthropic_stop_reason_is_stop(stop_reason)))
+}
+
+pub fn anthropic_stop_reason_is_stop(stop_reason: &str) -> bool {
+    stop_reason == "end_turn" || stop_reason == "stop_sequence"
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use mockito::{mock, Matcher};
+    use reqwest_eventsource::Event;
+
+    use super::*;
+
+    fn chat_prompt() -> String {
+        let messages = vec![
+            call_validation::ChatMessage { role: "system".to_string(), content: "be brief".to_string() },
+            call_validation::ChatMessage { role: "context_file".to_string(), content: "main.py".to_string() },
+            call_validation::ChatMessage { role: "user".to_string(), content: "hello".to_string() },
+            call_validation::ChatMessage { role: "assistant".to_string(), content: "hi".to_string() },
+            call_validation::ChatMessage { role: "user".to_string(), content: "bye".to_string() },
+        ];
+        format!("PASSTHROUGH {}", serde_json::to_string(&messages).unwrap())
+    }
+
+    fn params() -> SamplingParameters {
+        SamplingParameters {
+            max_new_tokens: 20,
+            temperature: Some(0.2),
+            top_p: None,
+            stop: Some(vec!["\n\n".to_string(), "</s>".to_string()]),
+            n: None,
+        }
+    }
+
+    #[test]
+    fn test_messages_mapping() {
+        let data = _make_data("claude", &chat_prompt(), &params(), false).unwrap();
+        assert_eq!(data["system"], json!("be brief"));
+        assert_eq!(data["stop_sequences"], json!(["</s>"]));
+        assert_eq!(data["messages"], json!([
+            {"role": "user", "content": [{"type": "text", "text": "main.py"}, {"type": "text", "text": "hello"}]},
+            {"role": "assistant", "content": [{"type": "text", "text": "hi"}]},
+            {"role": "user", "content": [{"type": "text", "text": "bye"}]},
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_not_stream() {
+        let _m = mock("POST", "/v1/messages")
+            .match_header("x-api-key", "secret")
+            .match_header("anthropic-version", ANTHROPIC_VERSION)
+            .match_body(Matcher::PartialJson(json!({"model": "claude", "stream": false, "system": "be brief"})))
+            .with_status(200)
+            .with_body(r#"{"type": "message", "role": "assistant", "content": [{"type": "text", "text": "see "}, {"type": "text", "text": "you"}], "stop_reason": "end_turn"}"#)
+            .create();
+        let mut save_url = String::new();
+        let endpoint = format!("{}/v1/messages", mockito::server_url());
+        let model_says = forward_to_anthropic_style_endpoint(
+            &mut save_url,
+            "secret".to_string(),
+            "claude",
+            &chat_prompt(),
+            &reqwest::Client::new(),
+            &endpoint,
+            &"".to_string(),
+            &params(),
+        ).await.unwrap();
+        assert_eq!(save_url, endpoint);
+        assert_eq!(anthropic_response_text(&model_says), Some(("see you".to_string(), true)));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let sse = concat!(
+            "event: message_start\ndata: {\"type\": \"message_start\", \"message\": {\"role\": \"assistant\", \"content\": []}}\n\n",
+            "event: content_block_start\ndata: {\"type\": \"content_block_start\", \"index\": 0, \"content_block\": {\"type\": \"text\", \"text\": \"\"}}\n\n",
+            "event: ping\ndata: {\"type\": \"ping\"}\n\n",
+            "event: content_block_delta\ndata: {\"type\": \"content_block_delta\", \"index\": 0, \"delta\": {\"type\": \"text_delta\", \"text\": \"see\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\": \"content_block_delta\", \"index\": 0, \"delta\": {\"type\": \"text_delta\", \"text\": \" you\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\": \"content_block_stop\", \"index\": 0}\n\n",
+            "event: message_delta\ndata: {\"type\": \"message_delta\", \"delta\": {\"stop_reason\": \"max_tokens\"}}\n\n",
+            "event: message_stop\ndata: {\"type\": \"message_stop\"}\n\n",
+        );
+        let _m = mock("POST", "/v1/stream")
+            .match_body(Matcher::PartialJson(json!({"model": "claude", "stream": true})))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(sse)
+            .create();
+        let mut save_url = String::new();
+        let mut event_source = forward_to_anthropic_style_endpoint_streaming(
+            &mut save_url,
+            "secret".to_string(),
+            "claude",
+            "def hello",
+            &reqwest::Client::new(),
+            &format!("{}/v1/stream", mockito::server_url()),
+            &"".to_string(),
+            &params(),
+        ).await.unwrap();
+        let mut events: Vec<String> = vec![];
+        let mut text = String::new();
+        let mut stop_reason = String::new();
+        while let Some(event) = event_source.next().await {
+            match event {
+                Ok(Event::Open) => {}
+                Ok(Event::Message(message)) => {
+                    let json: serde_json::Value = serde_json::from_str(&message.data).unwrap();
+                    assert_eq!(json["type"], json!(message.event));
+                    text.push_str(json["delta"]["text"].as_str().unwrap_or(""));
+                    stop_reason.push_str(json["delta"]["stop_reason"].as_str().unwrap_or(""));
+                    let finished = message.event == "message_stop";
+                    events.push(message.event);
+                    if finished {
+                        break;
+                    }
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert_eq!(events.len(), 8);
+        assert_eq!(text, "see you");
+        assert!(!anthropic_stop_reason_is_stop(&stop_reason));
+    }
+}
@@ -34,6 +34,12 @@ pub async fn forward_to_hf_style_endpoint(
     let params_string = serde_json::to_string(sampling_parameters).unwrap();
     let mut params_json = serde_json::from_str::<serde_json::Value>(&params_string).unwrap();
     params_json["return_full_text"] = serde_json::Value::Bool(false);
+    // HF calls it differently, each sequence comes back as a separate {"generated_text": ...}
+    if let Some(n) = params_json.as_object_mut().unwrap().remove("n") {
+        if n.as_u64().unwrap_or(1) > 1 {
+            params_json["num_return_sequences"] = n;
+        }
+    }
 
     let data = json!({
         "inputs": prompt,
@@ -78,6 +84,7 @@ pub async fn forward_to_hf_style_endpoint_streaming(
     let params_string = serde_json::to_string(sampling_parameters).unwrap();
     let mut params_json = serde_json::from_str::<serde_json::Value>(&params_string).unwrap();
     params_json["return_full_text"] = serde_json::Value::Bool(false);
+    params_json.as_object_mut().unwrap().remove("n");  // streaming is always one candidate
 
     let data = json!({
         "inputs": prompt,
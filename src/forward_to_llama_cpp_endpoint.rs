@@ -0,0 +1,141 @@
+use serde_json::json;
+
+use crate::call_validation::SamplingParameters;
+use crate::forward_ndjson::{ndjson_stream, post_and_check_status, NdjsonStream};
+
+
+fn _make_data(
+    prompt: &str,
+    sampling_parameters: &SamplingParameters,
+    stream: bool,
+) -> serde_json::Value {
+    let mut data = json!({
+        "prompt": prompt,
+        "n_predict": sampling_parameters.max_new_tokens,
+        "stream": stream,
+        "cache_prompt": true,
+    });
+    if let Some(temperature) = sampling_parameters.temperature {
+        data["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = sampling_parameters.top_p {
+        data["top_p"] = json!(top_p);
+    }
+    if let Some(stop) = &sampling_parameters.stop {
+        data["stop"] = json!(stop);
+    }
+    data
+}
+
+// llama.cpp server runs one model, the name only goes into the url if the template asks for it
+pub async fn forward_to_llama_cpp_style_endpoint(
+    save_url: &mut String,
+    bearer: String,
+    model_name: &str,
+    prompt: &str,
+    client: &reqwest::Client,
+    endpoint_template: &String,
+    sampling_parameters: &SamplingParameters,
+) -> Result<serde_json::Value, String> {
+    let url = endpoint_template.replace("$MODEL", model_name);
+    save_url.clone_from(&&url);
+    let data = _make_data(prompt, sampling_parameters, false);
+    let resp = post_and_check_status(&url, &bearer, client, &data).await?;
+    let response_txt = resp.text().await.map_err(|e|
+        format!("reading from socket {}: {}", url, e)
+    )?;
+    serde_json::from_str(&response_txt).map_err(|e| format!("{}: {}", url, e))
+}
+
+pub async fn forward_to_llama_cpp_style_endpoint_streaming(
+    save_url: &mut String,
+    bearer: String,
+    model_name: &str,
+    prompt: &str,
+    client: &reqwest::Client,
+    endpoint_template: &String,
+    sampling_parameters: &SamplingParameters,
+) -> Result<NdjsonStream, String> {
+    let url = endpoint_template.replace("$MODEL", model_name);
+    save_url.clone_from(&&url);
+    let data = _make_data(prompt, sampling_parameters, true);
+    let resp = post_and_check_status(&url, &bearer, client, &data).await?;
+    Ok(ndjson_stream(resp))
+}
+
+// {"content": "...", "stop": true, "stopped_limit": false} -> text and whether the model stopped by itself
+pub fn llama_cpp_response_text(model_says: &serde_json::Value) -> Option<(String, bool)> {
+    let text = model_says.get("content")?.as_str()?.to_string();
+    let stopped_limit = model_says.get("stopped_limit").and_then(|x| x.as_bool()).unwrap_or(false);
+    Some((text, !stopped_limit))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use mockito::{mock, Matcher};
+
+    use super::*;
+
+    fn params() -> SamplingParameters {
+        SamplingParameters {
+            max_new_tokens: 20,
+            temperature: None,
+            top_p: None,
+            stop: Some(vec!["<|endoftext|>".to_string()]),
+            n: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_stream() {
+        let _m = mock("POST", "/completion")
+            .match_body(Matcher::PartialJson(json!({
+                "prompt": "<fim_prefix>def f(",
+                "n_predict": 20,
+                "stream": false,
+                "stop": ["<|endoftext|>"],
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": "x):", "stop": true, "stopped_eos": true, "stopped_limit": false}"#)
+            .create();
+        let mut save_url = String::new();
+        let model_says = forward_to_llama_cpp_style_endpoint(
+            &mut save_url,
+            "".to_string(),
+            "starcoder2/3b/base",
+            "<fim_prefix>def f(",
+            &reqwest::Client::new(),
+            &format!("{}/completion", mockito::server_url()),
+            &params(),
+        ).await.unwrap();
+        assert_eq!(llama_cpp_response_text(&model_says), Some(("x):".to_string(), true)));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let _m = mock("POST", "/completion")
+            .match_body(Matcher::PartialJson(json!({"stream": true})))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body("data: {\"content\": \"x\", \"stop\": false}\n\ndata: {\"content\": \"):\", \"stop\": false}\n\ndata: {\"content\": \"\", \"stop\": true, \"stopped_limit\": true}\n\n")
+            .create();
+        let mut save_url = String::new();
+        let lines = forward_to_llama_cpp_style_endpoint_streaming(
+            &mut save_url,
+            "".to_string(),
+            "starcoder2/3b/base",
+            "<fim_prefix>def f(",
+            &reqwest::Client::new(),
+            &format!("{}/completion", mockito::server_url()),
+            &params(),
+        ).await.unwrap().collect::<Vec<_>>().await;
+        let jsons = lines.into_iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(&line.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(jsons.len(), 3);
+        assert_eq!(jsons.iter().map(|j| j["content"].as_str().unwrap()).collect::<String>(), "x):");
+        assert_eq!(jsons[2]["stopped_limit"], json!(true));
+    }
+}
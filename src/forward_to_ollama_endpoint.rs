@@ -0,0 +1,166 @@
+use serde_json::json;
+
+use crate::call_validation::SamplingParameters;
+use crate::forward_ndjson::{ndjson_stream, post_and_check_status, NdjsonStream};
+
+
+fn _make_data(
+    model_name: &str,
+    prompt: &str,
+    sampling_parameters: &SamplingParameters,
+    stream: bool,
+) -> serde_json::Value {
+    let mut options = json!({
+        "num_predict": sampling_parameters.max_new_tokens,
+    });
+    if let Some(temperature) = sampling_parameters.temperature {
+        options["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = sampling_parameters.top_p {
+        options["top_p"] = json!(top_p);
+    }
+    if let Some(stop) = &sampling_parameters.stop {
+        options["stop"] = json!(stop);
+    }
+    // raw: the scratchpad already built the prompt (FIM tokens or a chat template), don't apply the model template
+    json!({
+        "model": model_name,
+        "prompt": prompt,
+        "raw": true,
+        "stream": stream,
+        "options": options,
+    })
+}
+
+pub async fn forward_to_ollama_style_endpoint(
+    save_url: &mut String,
+    bearer: String,
+    model_name: &str,
+    prompt: &str,
+    client: &reqwest::Client,
+    endpoint_template: &String,
+    sampling_parameters: &SamplingParameters,
+) -> Result<serde_json::Value, String> {
+    let url = endpoint_template.replace("$MODEL", model_name);
+    save_url.clone_from(&&url);
+    let data = _make_data(model_name, prompt, sampling_parameters, false);
+    let resp = post_and_check_status(&url, &bearer, client, &data).await?;
+    let response_txt = resp.text().await.map_err(|e|
+        format!("reading from socket {}: {}", url, e)
+    )?;
+    serde_json::from_str(&response_txt).map_err(|e| format!("{}: {}", url, e))
+}
+
+pub async fn forward_to_ollama_style_endpoint_streaming(
+    save_url: &mut String,
+    bearer: String,
+    model_name: &str,
+    prompt: &str,
+    client: &reqwest::Client,
+    endpoint_template: &String,
+    sampling_parameters: &SamplingParameters,
+) -> Result<NdjsonStream, String> {
+    let url = endpoint_template.replace("$MODEL", model_name);
+    save_url.clone_from(&&url);
+    let data = _make_data(model_name, prompt, sampling_parameters, true);
+    let resp = post_and_check_status(&url, &bearer, client, &data).await?;
+    Ok(ndjson_stream(resp))
+}
+
+// {"response": "...", "done": true, "done_reason": "stop"} -> text and whether the model stopped by itself
+pub fn ollama_response_text(model_says: &serde_json::Value) -> Option<(String, bool)> {
+    let text = model_says.get("response")?.as_str()?.to_string();
+    let done_reason = model_says.get("done_reason").and_then(|x| x.as_str()).unwrap_or("");
+    Some((text, done_reason != "length"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use mockito::{mock, Matcher};
+
+    use super::*;
+
+    fn params() -> SamplingParameters {
+        SamplingParameters {
+            max_new_tokens: 20,
+            temperature: Some(0.2),
+            top_p: None,
+            stop: Some(vec!["\n\n".to_string()]),
+            n: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_stream() {
+        let _m = mock("POST", "/api/generate")
+            .match_body(Matcher::PartialJson(json!({
+                "model": "starcoder2:3b",
+                "prompt": "<fim_prefix>def f(",
+                "raw": true,
+                "stream": false,
+                "options": {"num_predict": 20, "stop": ["\n\n"]},
+            })))
+            .with_status(200)
+            .with_body(r#"{"model": "starcoder2:3b", "response": "x):", "done": true, "done_reason": "length"}"#)
+            .create();
+        let mut save_url = String::new();
+        let model_says = forward_to_ollama_style_endpoint(
+            &mut save_url,
+            "".to_string(),
+            "starcoder2:3b",
+            "<fim_prefix>def f(",
+            &reqwest::Client::new(),
+            &format!("{}/api/generate", mockito::server_url()),
+            &params(),
+        ).await.unwrap();
+        assert_eq!(ollama_response_text(&model_says), Some(("x):".to_string(), false)));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let _m = mock("POST", "/api/generate")
+            .match_body(Matcher::PartialJson(json!({"stream": true})))
+            .with_status(200)
+            .with_header("content-type", "application/x-ndjson")
+            .with_body("{\"response\": \"x\", \"done\": false}\n\n{\"response\": \"):\", \"done\": false}\n{\"response\": \"\", \"done\": true, \"done_reason\": \"stop\"}")
+            .create();
+        let mut save_url = String::new();
+        let lines = forward_to_ollama_style_endpoint_streaming(
+            &mut save_url,
+            "".to_string(),
+            "starcoder2:3b",
+            "<fim_prefix>def f(",
+            &reqwest::Client::new(),
+            &format!("{}/api/generate", mockito::server_url()),
+            &params(),
+        ).await.unwrap().collect::<Vec<_>>().await;
+        let jsons = lines.into_iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(&line.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(jsons.len(), 3);
+        assert_eq!(jsons.iter().map(|j| j["response"].as_str().unwrap()).collect::<String>(), "x):");
+        assert_eq!(jsons[2]["done"], json!(true));
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let _m = mock("POST", "/api/generate")
+            .with_status(404)
+            .with_body(r#"{"error": "model 'nope' not found"}"#)
+            .create();
+        let mut save_url = String::new();
+        let err = forward_to_ollama_style_endpoint_streaming(
+            &mut save_url,
+            "".to_string(),
+            "nope",
+            "def f(",
+            &reqwest::Client::new(),
+            &format!("{}/api/generate", mockito::server_url()),
+            &params(),
+        ).await.err().unwrap();
+        assert!(err.contains("status=404"));
+        assert!(err.contains("not found"));
+    }
+}
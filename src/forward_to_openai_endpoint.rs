@@ -43,6 +43,11 @@ pub async fn forward_to_openai_style_endpoint(
         _passthrough_messages_to_json(&mut data, prompt);
     } else {
         data["prompt"] = serde_json::Value::String(prompt.to_string());
+        // logprobs are used to rank the candidates
+        if let Some(n) = sampling_parameters.n.filter(|n| *n > 1) {
+            data["n"] = json!(n);
+            data["logprobs"] = json!(1);
+        }
     }
     // When cancelling requests, coroutine ususally gets aborted here on the following line.
     let req = client.post(&url)
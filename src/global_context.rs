@@ -6,12 +6,13 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex as StdMutex;
 use std::sync::RwLock as StdRwLock;
+use std::time::{Duration, Instant};
 
 use hyper::StatusCode;
 use structopt::StructOpt;
 use tokenizers::Tokenizer;
 use tokio::signal;
-use tokio::sync::{Mutex as AMutex, Semaphore};
+use tokio::sync::{Mutex as AMutex, Notify, Semaphore};
 use tokio::sync::RwLock as ARwLock;
 use tracing::{error, info};
 use url::Url;
@@ -19,6 +20,8 @@ use url::Url;
 use crate::ast::ast_module::AstModule;
 use crate::caps::CodeAssistantCaps;
 use crate::completion_cache::CompletionCache;
+use crate::completion_cache_disk::CompletionCacheDisk;
+use crate::completion_in_flight::CompletionsInFlight;
 use crate::custom_error::ScratchError;
 use crate::files_in_workspace::Document;
 use crate::telemetry::telemetry_structs;
@@ -58,6 +61,14 @@ pub struct CommandLine {
     pub vecdb_forced_path: String,
     #[structopt(long, short="w", default_value="", help="Workspace folder to find files for vecdb and AST. An LSP or HTTP request can override this later.")]
     pub workspace_folder: String,
+    #[structopt(long, default_value="", help="A directory with <model>/tokenizer.json files, used before trying to download a tokenizer. Useful on machines without network access.")]
+    pub tokenizers_dir: String,
+    #[structopt(long, default_value="", help="Copy <model>/tokenizer.json files from this directory into the cache dir at start, so they don't need to be downloaded.")]
+    pub import_tokenizers: String,
+    #[structopt(long, default_value="0", help="Keep completions in an SQLite file in the cache dir up to this many megabytes, so they survive a restart. Zero means memory only.")]
+    pub completion_cache_disk_mb: usize,
+    #[structopt(long, default_value="604800", help="Completions on disk older than this many seconds are not used.")]
+    pub completion_cache_ttl: u64,
 }
 impl CommandLine {
     fn create_hash(msg: String) -> String {
@@ -70,10 +81,19 @@ impl CommandLine {
     }
 }
 
+// Connected LSP clients, to send them notifications. Ids are never reused, a completion of a gone client
+// must not supersede one of a new client.
+#[derive(Default)]
+pub struct LspClients {
+    pub next_client_id: u64,
+    pub clients: HashMap<String, tower_lsp::Client>,
+}
+
 pub struct DocumentsState {
     pub workspace_folders: Arc<StdMutex<Vec<PathBuf>>>,
     pub workspace_files: Arc<StdMutex<Vec<Url>>>,
     pub document_map: Arc<ARwLock<HashMap<Url, Document>>>,   // if a file is open in IDE and it's outside workspace dirs, it will be in this map and not in workspace_files
+    pub document_touched: Arc<StdMutex<HashMap<Url, Instant>>>,  // when a document was last opened or changed in IDE
 }
 
 pub struct GlobalContext {
@@ -86,19 +106,24 @@ pub struct GlobalContext {
     pub caps_last_error: String,
     pub caps_last_attempted_ts: u64,
     pub tokenizer_map: HashMap< String, Arc<StdRwLock<Tokenizer>>>,
+    pub tokenizer_approximate_retry_ts: HashMap<String, u64>,  // models with an approximate tokenizer, when to try the real one again
     pub tokenizer_download_lock: Arc<AMutex<bool>>,
     pub completions_cache: Arc<StdRwLock<CompletionCache>>,
+    pub completions_in_flight: Arc<StdMutex<CompletionsInFlight>>,
     pub telemetry: Arc<StdRwLock<telemetry_structs::Storage>>,
     pub vec_db: Arc<AMutex<Option<VecDb>>>,
     pub ast_module: Arc<AMutex<Option<AstModule>>>,   // TODO: don't use AMutex, use StdMutex
     pub ask_shutdown_sender: Arc<StdMutex<std::sync::mpsc::Sender<String>>>,
     pub documents_state: DocumentsState,
+    pub caps_version_check_now: Arc<Notify>,
+    pub lsp_clients: Arc<StdMutex<LspClients>>,
 }
 
 pub type SharedGlobalContext = Arc<ARwLock<GlobalContext>>;  // TODO: remove this type alias, confusing
 
 const CAPS_RELOAD_BACKOFF: u64 = 60;       // seconds
 const CAPS_BACKGROUND_RELOAD: u64 = 3600;  // seconds
+const CAPS_VERSION_POLL: u64 = 60;         // seconds
 
 pub async fn try_load_caps_quickly_if_not_present(
     global_context: Arc<ARwLock<GlobalContext>>,
@@ -165,14 +190,77 @@ pub async fn look_for_piggyback_fields(
                 let caps_locked = caps.read().unwrap();
                 if caps_locked.caps_version < new_caps_version {
                     info!("detected biggyback caps version {} is newer than the current version {}", new_caps_version, caps_locked.caps_version);
-                    global_context_locked.caps = None;
-                    global_context_locked.caps_last_attempted_ts = 0;
+                    global_context_locked.caps_version_check_now.notify_one();
                 }
             }
         }
     }
 }
 
+async fn _reload_caps_if_version_changed(
+    global_context: Arc<ARwLock<GlobalContext>>,
+) -> Result<(), String> {
+    let (old_caps, caps_reading_lock) = {
+        let cx_locked = global_context.read().await;
+        (cx_locked.caps.clone(), cx_locked.caps_reading_lock.clone())
+    };
+    let old_caps = match old_caps {
+        Some(x) => x,
+        None => return Ok(()),  // not loaded yet, try_load_caps_quickly_if_not_present will do it
+    };
+    let cmdline = CommandLine::from_args();
+    let (old_version, etag, last_modified) = {
+        let caps_locked = old_caps.read().unwrap();
+        (caps_locked.caps_version, caps_locked.caps_etag.clone(), caps_locked.caps_last_modified.clone())
+    };
+    let (new_version, caps_buffer) = match crate::caps::fetch_caps_if_changed(&cmdline, global_context.clone(), &etag, &last_modified).await? {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    if new_version <= old_version {
+        // changed without a version bump, not worth a reload, but no need to download it again either
+        let mut caps_locked = old_caps.write().unwrap();
+        caps_locked.caps_etag = caps_buffer.etag;
+        caps_locked.caps_last_modified = caps_buffer.last_modified;
+        return Ok(());
+    }
+    info!("caps version {} -> {}, reloading", old_version, new_version);
+    let (changed_models, tokenizer_changed) = {
+        let _caps_reading_locked = caps_reading_lock.lock().await;
+        let new_caps = crate::caps::load_caps_from_buffer(caps_buffer, global_context.clone()).await?;
+        let changes = crate::caps::models_changed(&old_caps.read().unwrap(), &new_caps.read().unwrap());
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let mut cx_locked = global_context.write().await;
+        cx_locked.caps = Some(new_caps);
+        cx_locked.caps_last_attempted_ts = now;
+        cx_locked.caps_last_error = "".to_string();
+        changes
+    };
+    crate::cached_tokenizers::forget_tokenizers(global_context.clone(), &changed_models, &tokenizer_changed).await;
+    let cache_arc = global_context.read().await.completions_cache.clone();
+    let removed = crate::completion_cache::cache_invalidate_models(cache_arc, &changed_models).await;
+    info!("caps reloaded, changed models {:?}, {} completion cache entries removed", changed_models, removed);
+    let mut changed_models = changed_models.into_iter().collect::<Vec<_>>();
+    changed_models.sort();
+    crate::lsp::notify_caps_changed(global_context.clone(), new_version, changed_models).await;
+    Ok(())
+}
+
+pub async fn caps_version_background_task(
+    global_context: Arc<ARwLock<GlobalContext>>,
+) {
+    let check_now = global_context.read().await.caps_version_check_now.clone();
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(tokio::time::Duration::from_secs(CAPS_VERSION_POLL)) => {},
+            _ = check_now.notified() => {},
+        }
+        if let Err(e) = _reload_caps_if_version_changed(global_context.clone()).await {
+            error!("caps version check failed: {}", e);
+        }
+    }
+}
+
 pub async fn block_until_signal(ask_shutdown_receiver: std::sync::mpsc::Receiver<String>) {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -226,6 +314,17 @@ pub async fn create_global_context(
         http_client_builder = http_client_builder.danger_accept_invalid_certs(true)
     }
     let http_client = http_client_builder.build().unwrap();
+    let mut completions_cache = CompletionCache::new();
+    if cmdline.completion_cache_disk_mb > 0 {
+        match CompletionCacheDisk::open(
+            &cache_dir.join("completion_cache.sqlite"),
+            cmdline.completion_cache_disk_mb * 1024 * 1024,
+            Duration::from_secs(cmdline.completion_cache_ttl),
+        ) {
+            Ok(disk) => completions_cache.disk = Some(Arc::new(disk)),
+            Err(e) => error!("completion cache on disk is not available: {}", e),
+        }
+    }
     let cx = GlobalContext {
         cmdline: cmdline.clone(),
         http_client,
@@ -236,8 +335,10 @@ pub async fn create_global_context(
         caps_last_error: String::new(),
         caps_last_attempted_ts: 0,
         tokenizer_map: HashMap::new(),
+        tokenizer_approximate_retry_ts: HashMap::new(),
         tokenizer_download_lock: Arc::new(AMutex::<bool>::new(false)),
-        completions_cache: Arc::new(StdRwLock::new(CompletionCache::new())),
+        completions_cache: Arc::new(StdRwLock::new(completions_cache)),
+        completions_in_flight: Arc::new(StdMutex::new(CompletionsInFlight::default())),
         telemetry: Arc::new(StdRwLock::new(telemetry_structs::Storage::new())),
         vec_db: Arc::new(AMutex::new(None)),
         ast_module: Arc::new(AMutex::new(None)),
@@ -246,7 +347,10 @@ pub async fn create_global_context(
             workspace_folders: if cmdline.workspace_folder.is_empty() { Arc::new(StdMutex::new(vec![])) } else { Arc::new(StdMutex::new(vec![PathBuf::from(cmdline.workspace_folder.clone())])) },
             workspace_files: Arc::new(StdMutex::new(vec![])),
             document_map: Arc::new(ARwLock::new(HashMap::new())),
+            document_touched: Arc::new(StdMutex::new(HashMap::new())),
         },
+        caps_version_check_now: Arc::new(Notify::new()),
+        lsp_clients: Arc::new(StdMutex::new(LspClients::default())),
     };
     let gcx = Arc::new(ARwLock::new(cx));
     if cmdline.ast {
@@ -18,6 +18,7 @@ use crate::http::routers::v1::ast::{handle_v1_ast_declarations_cursor_search, ha
 use crate::http::routers::v1::caps::handle_v1_caps;
 use crate::http::routers::v1::chat::handle_v1_chat;
 use crate::http::routers::v1::code_completion::handle_v1_code_completion_web;
+use crate::http::routers::v1::completion_cache::{handle_v1_completion_cache_clear, handle_v1_completion_cache_status};
 use crate::http::routers::v1::graceful_shutdown::handle_v1_graceful_shutdown;
 use crate::http::routers::v1::snippet_accepted::handle_v1_snippet_accepted;
 use crate::http::routers::v1::telemetry_network::handle_v1_telemetry_network;
@@ -31,6 +32,7 @@ use crate::http::routers::v1::vecdb::{handle_v1_vecdb_search, handle_v1_vecdb_st
 use crate::http::routers::v1::at_commands::{handle_v1_command_completion, handle_v1_command_preview};
 
 pub mod code_completion;
+pub mod completion_cache;
 pub mod chat;
 pub mod telemetry_network;
 pub mod snippet_accepted;
@@ -46,6 +48,8 @@ mod ast;
 pub fn make_v1_router() -> Router {
     Router::new()
         .route("/code-completion", telemetry_post!(handle_v1_code_completion_web))
+        .route("/completion-cache", telemetry_get!(handle_v1_completion_cache_status))
+        .route("/completion-cache-clear", telemetry_post!(handle_v1_completion_cache_clear))
         .route("/chat", telemetry_post!(handle_v1_chat))
         .route("/telemetry-network", telemetry_post!(handle_v1_telemetry_network))
         .route("/snippet-accepted", telemetry_post!(handle_v1_snippet_accepted))
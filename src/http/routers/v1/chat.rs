@@ -83,5 +83,6 @@ pub async fn handle_v1_chat(
         client1,
         api_key,
         chat_post.parameters.clone(),
+        None,
     ).await
 }
\ No newline at end of file
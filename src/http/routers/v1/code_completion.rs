@@ -11,9 +11,11 @@ use crate::call_validation::{CodeCompletionPost, validate_post};
 use crate::caps;
 use crate::caps::CodeAssistantCaps;
 use crate::completion_cache;
+use crate::completion_in_flight;
 use crate::custom_error::ScratchError;
 use crate::global_context::GlobalContext;
 use crate::scratchpads;
+use crate::telemetry::snippets_collection;
 
 async fn _lookup_code_completion_scratchpad(
     caps: Arc<StdRwLock<CodeAssistantCaps>>,
@@ -62,15 +64,31 @@ pub async fn handle_v1_code_completion(
         code_completion_post.scratchpad = scratchpad_name.clone();
     }
     code_completion_post.parameters.temperature = Some(code_completion_post.parameters.temperature.unwrap_or(0.2));
-    let (client1, api_key, cache_arc, tele_storage) = {
+    let (client1, api_key, cache_arc, tele_storage, in_flight_arc) = {
         let cx_locked = global_context.write().await;
-        (cx_locked.http_client.clone(), cx_locked.cmdline.api_key.clone(), cx_locked.completions_cache.clone(), cx_locked.telemetry.clone())
+        (cx_locked.http_client.clone(), cx_locked.cmdline.api_key.clone(), cx_locked.completions_cache.clone(), cx_locked.telemetry.clone(), cx_locked.completions_in_flight.clone())
     };
+    // before the cache lookup, a cache hit supersedes the requests still waiting for the model as well
+    let mut in_flight = completion_in_flight::completion_started(
+        in_flight_arc,
+        tele_storage.clone(),
+        &code_completion_post.client_id,
+        &code_completion_post.inputs.cursor.file,
+    );
     if !code_completion_post.no_cache {
         let cache_key = completion_cache::cache_key_from_post(&code_completion_post);
-        let cached_maybe = completion_cache::cache_get(cache_arc.clone(), cache_key.clone());
+        let mut cached_maybe = completion_cache::cache_get(cache_arc.clone(), cache_key.clone());
+        if cached_maybe.is_none() {
+            cached_maybe = completion_cache::cache_get_disk(cache_arc.clone(), cache_key.clone(), &code_completion_post.model).await.map(|mut value| {
+                let ss = snippets_collection::SaveSnippet::new(tele_storage.clone(), &code_completion_post);
+                snippets_collection::snippet_register_from_disk_cache(&ss, &mut value);
+                completion_cache::cache_put_memory(cache_arc.clone(), cache_key.clone(), value.clone());
+                value
+            });
+        }
         if let Some(cached_json_value) = cached_maybe {
             // info!("cache hit for key {:?}", cache_key.clone());
+            in_flight.finish();
             if !code_completion_post.stream {
                 return crate::restream::cached_not_stream(&cached_json_value).await;
             } else {
@@ -79,36 +97,59 @@ pub async fn handle_v1_code_completion(
         }
     }
 
-    let ast_module = global_context.read().await.ast_module.clone();
-    let mut scratchpad = scratchpads::create_code_completion_scratchpad(
-        global_context.clone(),
-        caps,
-        model_name.clone(),
-        code_completion_post.clone(),
-        &scratchpad_name,
-        &scratchpad_patch,
-        cache_arc.clone(),
-        tele_storage.clone(),
-        ast_module
-    ).await.map_err(|e|
-        ScratchError::new(StatusCode::BAD_REQUEST, e)
-    )?;
-    let t1 = std::time::Instant::now();
-    let prompt = scratchpad.prompt(
-        n_ctx,
-        &mut code_completion_post.parameters,
-    ).await.map_err(|e|
-        ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Prompt: {}", e))
-    )?;
-    // info!("prompt {:?}\n{}", t1.elapsed(), prompt);
-    info!("prompt {:?}", t1.elapsed());
+    let prepared = in_flight.run_unless_superseded(async {
+        let ast_module = global_context.read().await.ast_module.clone();
+        let mut scratchpad = scratchpads::create_code_completion_scratchpad(
+            global_context.clone(),
+            caps,
+            model_name.clone(),
+            code_completion_post.clone(),
+            &scratchpad_name,
+            &scratchpad_patch,
+            cache_arc.clone(),
+            tele_storage.clone(),
+            ast_module
+        ).await.map_err(|e|
+            ScratchError::new(StatusCode::BAD_REQUEST, e)
+        )?;
+        let t1 = std::time::Instant::now();
+        let prompt = scratchpad.prompt(
+            n_ctx,
+            &mut code_completion_post.parameters,
+        ).await.map_err(|e|
+            ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Prompt: {}", e))
+        )?;
+        // info!("prompt {:?}\n{}", t1.elapsed(), prompt);
+        info!("prompt {:?}", t1.elapsed());
+        Ok::<_, ScratchError>((scratchpad, prompt))
+    }).await;
+    let (scratchpad, prompt) = match prepared {
+        Some(Ok(x)) => x,
+        Some(Err(e)) => {
+            in_flight.finish();
+            return Err(e);
+        }
+        None => return Err(_superseded()),
+    };
     if !code_completion_post.stream {
-        crate::restream::scratchpad_interaction_not_stream(global_context.clone(), scratchpad, "completion".to_string(), &prompt, model_name, client1, api_key, &code_completion_post.parameters).await
+        let response = in_flight.run_unless_superseded(
+            crate::restream::scratchpad_interaction_not_stream(global_context.clone(), scratchpad, "completion".to_string(), &prompt, model_name, client1, api_key, &code_completion_post.parameters)
+        ).await;
+        in_flight.finish();
+        response.unwrap_or_else(|| Err(_superseded()))
     } else {
-        crate::restream::scratchpad_interaction_stream(global_context.clone(), scratchpad, "completion-stream".to_string(), prompt, model_name, client1, api_key, code_completion_post.parameters.clone()).await
+        // the guard goes into the stream, it lives as long as the upstream request
+        crate::restream::scratchpad_interaction_stream(global_context.clone(), scratchpad, "completion-stream".to_string(), prompt, model_name, client1, api_key, code_completion_post.parameters.clone(), Some(in_flight)).await
     }
 }
 
+fn _superseded() -> ScratchError {
+    ScratchError::new_but_skip_telemetry(
+        StatusCode::CONFLICT,
+        "superseded by a newer completion request for the same file".to_string(),
+    )
+}
+
 pub async fn handle_v1_code_completion_web(
     Extension(global_context): Extension<Arc<ARwLock<GlobalContext>>>,
     body_bytes: hyper::body::Bytes,
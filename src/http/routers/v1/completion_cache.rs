@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+
+use axum::Extension;
+use axum::response::Result;
+use hyper::{Body, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::completion_cache;
+use crate::custom_error::ScratchError;
+use crate::global_context::SharedGlobalContext;
+
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CompletionCacheClearPost {
+    #[serde(default)]
+    model: Option<String>,
+}
+
+
+pub async fn handle_v1_completion_cache_status(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    _: hyper::body::Bytes,
+) -> Result<Response<Body>, ScratchError> {
+    let cache_arc = global_context.read().await.completions_cache.clone();
+    let (memory_entries, disk) = {
+        let cache_locked = cache_arc.read().unwrap();
+        (cache_locked.map.len(), cache_locked.disk.clone())
+    };
+    let disk_stats = match disk {
+        Some(disk) => tokio::task::spawn_blocking(move || disk.stats()).await
+            .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?,
+        None => serde_json::Value::Null,
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(serde_json::to_string_pretty(&json!({
+            "memory": {"entries": memory_entries},
+            "disk": disk_stats,
+        })).unwrap()))
+        .unwrap())
+}
+
+pub async fn handle_v1_completion_cache_clear(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    body_bytes: hyper::body::Bytes,
+) -> Result<Response<Body>, ScratchError> {
+    let post = if body_bytes.is_empty() {
+        CompletionCacheClearPost { model: None }
+    } else {
+        serde_json::from_slice::<CompletionCacheClearPost>(&body_bytes).map_err(|e| {
+            ScratchError::new(StatusCode::BAD_REQUEST, format!("JSON problem: {}", e))
+        })?
+    };
+    let cache_arc = global_context.read().await.completions_cache.clone();
+    let removed = match post.model {
+        Some(model) => completion_cache::cache_invalidate_models(cache_arc, &HashSet::from([model])).await,
+        None => completion_cache::cache_clear(cache_arc).await,
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(json!({"success": true, "removed": removed}).to_string()))
+        .unwrap())
+}
@@ -18,7 +18,18 @@ pub const KNOWN_MODELS: &str = r####"
                 "wizardcoder/15b",
                 "starcoder/1b/vllm",
                 "starcoder/3b/vllm",
-                "starcoder/7b/vllm",
+                "starcoder/7b/vllm"
+            ]
+        },
+        "bigcode/starcoder2-3b": {
+            "n_ctx": 4096,
+            "supports_scratchpads": {
+                "FIM-PSM": {},
+                "FIM-SPM": {},
+                "FIM-REPO": {}
+            },
+            "default_scratchpad": "FIM-PSM",
+            "similar_models": [
                 "starcoder2/3b/base",
                 "starcoder2/7b/base",
                 "starcoder2/15b/base",
@@ -48,6 +59,17 @@ pub const KNOWN_MODELS: &str = r####"
                     "fim_middle": "<MID>",
                     "eot": "<EOT>",
                     "eos": "</s>"
+                },
+                "FIM-SENTINELS": {
+                    "fim_prefix": "<PRE> ",
+                    "fim_suffix": " <SUF>",
+                    "fim_middle": " <MID>",
+                    "eot": "<EOT>",
+                    "eos": "</s>"
+                },
+                "PREFIX-ONLY": {
+                    "eot": "</s>",
+                    "eos": "</s>"
                 }
             },
             "default_scratchpad": "FIM-PSM",
@@ -1,19 +1,25 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 use tokio::sync::RwLock as ARwLock;
 use tokio::task::JoinHandle;
+use itertools::Itertools;
+use ropey::Rope;
 use tower_lsp::{ClientSocket, LanguageServer, LspService};
 use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::*;
 use tracing::{error, info};
+use tree_sitter::Point;
 
-use crate::call_validation::{CodeCompletionInputs, CodeCompletionPost, CursorPosition, SamplingParameters};
+use crate::ast::treesitter::structs::{SymbolDeclarationStruct, SymbolInfo, SymbolType};
+use crate::call_validation::{CodeCompletionInputs, CodeCompletionPost, CursorPosition, MAX_COMPLETION_CHOICES, SamplingParameters};
 use crate::files_in_workspace;
+use crate::files_in_workspace::DocumentInfo;
 use crate::global_context;
 use crate::global_context::CommandLine;
 use crate::http::routers::v1::code_completion::handle_v1_code_completion;
@@ -35,10 +41,40 @@ impl Display for APIError {
 }
 
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CapsChangedParams {
+    pub caps_version: i64,
+    pub changed_models: Vec<String>,
+}
+
+pub enum CapsChanged {}
+
+impl notification::Notification for CapsChanged {
+    type Params = CapsChangedParams;
+    const METHOD: &'static str = "refact/capsChanged";
+}
+
+pub async fn notify_caps_changed(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    caps_version: i64,
+    changed_models: Vec<String>,
+) {
+    let clients = gcx.read().await.lsp_clients.lock().unwrap().clients.values().cloned().collect::<Vec<_>>();
+    for client in clients {
+        client.send_notification::<CapsChanged>(CapsChangedParams {
+            caps_version,
+            changed_models: changed_models.clone(),
+        }).await;
+    }
+}
+
+
 // #[derive(Debug)]  GlobalContext does not implement Debug
 pub struct Backend {
     pub gcx: Arc<ARwLock<global_context::GlobalContext>>,
     pub client: tower_lsp::Client,
+    pub client_id: String,
+    pub n_choices: AtomicUsize,  // set from initializationOptions.completion_choices
 }
 
 
@@ -46,6 +82,8 @@ pub struct Backend {
 pub struct RequestParams {
     pub max_new_tokens: u32,
     pub temperature: f32,
+    #[serde(default)]
+    pub n: Option<usize>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -83,6 +121,71 @@ pub struct TestHeadTailAddedTextRes {
 }
 
 
+fn symbol_kind(symbol_type: &SymbolType) -> SymbolKind {
+    match symbol_type {
+        SymbolType::GlobalVar => SymbolKind::VARIABLE,
+        SymbolType::Function => SymbolKind::FUNCTION,
+        SymbolType::Class => SymbolKind::CLASS,
+        SymbolType::Enum => SymbolKind::ENUM_MEMBER,
+        SymbolType::Method => SymbolKind::METHOD,
+        SymbolType::Field => SymbolKind::FIELD,
+        SymbolType::Unknown => SymbolKind::NULL,
+    }
+}
+
+fn ts_range_to_lsp(range: &tree_sitter::Range) -> Range {
+    Range {
+        start: Position { line: range.start_point.row as u32, character: range.start_point.column as u32 },
+        end: Position { line: range.end_point.row as u32, character: range.end_point.column as u32 },
+    }
+}
+
+fn symbol_info_to_location(info: &SymbolInfo) -> Option<Location> {
+    let uri = files_in_workspace::pathbuf_to_url(&info.path).ok()?;
+    Some(Location { uri, range: ts_range_to_lsp(&info.range) })
+}
+
+// Parsers produce flat lists, nest symbols by meta_path: "file::Class::method" goes under "file::Class"
+fn document_symbols_tree(symbols: &Vec<SymbolDeclarationStruct>) -> Vec<DocumentSymbol> {
+    let keys: HashSet<&str> = symbols.iter().map(|s| s.meta_path.as_str()).collect();
+    let nearest_parent = |meta_path: &str| -> Option<String> {
+        let mut current = meta_path;
+        while let Some((parent, _)) = current.rsplit_once("::") {
+            if keys.contains(parent) {
+                return Some(parent.to_string());
+            }
+            current = parent;
+        }
+        None
+    };
+    let mut by_parent: HashMap<Option<String>, Vec<&SymbolDeclarationStruct>> = HashMap::new();
+    for symbol in symbols.iter() {
+        by_parent.entry(nearest_parent(&symbol.meta_path)).or_default().push(symbol);
+    }
+
+    #[allow(deprecated)]
+    fn build(parent: Option<String>, by_parent: &HashMap<Option<String>, Vec<&SymbolDeclarationStruct>>) -> Vec<DocumentSymbol> {
+        let mut symbols = by_parent.get(&parent).cloned().unwrap_or_default();
+        symbols.sort_by_key(|s| (s.definition_info.range.start_point.row, s.definition_info.range.start_point.column));
+        symbols.iter().map(|s| {
+            let range = ts_range_to_lsp(&s.definition_info.range);
+            let mut children = build(Some(s.meta_path.clone()), by_parent);
+            children.extend(document_symbols_tree(&s.children));
+            DocumentSymbol {
+                name: s.name.clone(),
+                detail: None,
+                kind: symbol_kind(&s.symbol_type),
+                tags: None,
+                deprecated: None,
+                range,
+                selection_range: range,
+                children: if children.is_empty() { None } else { Some(children) },
+            }
+        }).collect()
+    }
+    build(None, &by_parent)
+}
+
 fn internal_error<E: Display>(err: E) -> Error {
     let err_msg = err.to_string();
     error!(err_msg);
@@ -98,6 +201,8 @@ pub struct Choice {
     pub index: u32,
     pub code_completion: String,
     pub finish_reason: String,
+    #[serde(default)]
+    pub snippet_telemetry_id: Option<u64>,  // each candidate is a snippet of its own
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -114,6 +219,34 @@ pub struct SuccessRes {
     pub success: bool,
 }
 
+// textDocument/inlineCompletion is LSP 3.18, lsp_types we use doesn't have it yet
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InlineCompletionParams {
+    #[serde(flatten)]
+    pub text_document_position: TextDocumentPositionParams,
+    #[serde(default)]
+    pub context: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: String,
+    pub range: Option<Range>,
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct InlineCompletionList {
+    pub items: Vec<InlineCompletionItem>,
+}
+
+const LSP_MAX_NEW_TOKENS: u32 = 50;
+const LSP_TEMPERATURE: f32 = 0.2;
+const LSP_N_CHOICES: usize = 1;  // a client that can cycle through candidates asks for more in initializationOptions
+const ACCEPT_COMPLETION_COMMAND: &str = "refact.acceptCompletion";
+const AST_LSP_TOP_N: usize = 50;
+
 impl Backend {
     async fn flat_params_to_code_completion_post(&self, params: &CompletionParams1) -> Result<CodeCompletionPost> {
         let txt = {
@@ -145,6 +278,7 @@ impl Backend {
                 temperature: Option::from(params.parameters.temperature),
                 top_p: None,
                 stop: None,
+                n: params.parameters.n,
             },
             model: "".to_string(),
             scratchpad: "".to_string(),
@@ -152,6 +286,8 @@ impl Backend {
             no_cache: false,
             use_ast: false,
             use_vecdb: false,
+            no_postprocess: false,
+            client_id: self.client_id.clone(),
         })
     }
 
@@ -169,6 +305,101 @@ impl Backend {
         Ok(value)
     }
 
+    async fn get_line_prefix(&self, text_document_position: &TextDocumentPositionParams) -> Result<String> {
+        let document_map = self.gcx.read().await.documents_state.document_map.clone();
+        let document_map = document_map.read().await;
+        let doc = match document_map.get(&text_document_position.text_document.uri) {
+            Some(doc) => doc,
+            None => return Err(internal_error("document not found")),
+        };
+        let line = text_document_position.position.line as usize;
+        if line >= doc.text.len_lines() {
+            return Ok("".to_string());
+        }
+        // the position counts UTF-16 code units, not chars
+        let line_start = doc.text.line_to_char(line);
+        let cursor = files_in_workspace::lsp_position_to_char(&doc.text, &text_document_position.position);
+        Ok(doc.text.slice(line_start..cursor).to_string())
+    }
+
+    // Standard LSP clients don't send refact-specific parameters, decide multiline the same way plugins do
+    async fn get_completions_at_position(&self, mut text_document_position: TextDocumentPositionParams) -> Result<(CompletionRes, String)> {
+        let line_prefix = self.get_line_prefix(&text_document_position).await?;
+        // code completion counts chars in the cursor line
+        text_document_position.position.character = line_prefix.chars().count() as u32;
+        let params = CompletionParams1 {
+            text_document_position,
+            parameters: RequestParams {
+                max_new_tokens: LSP_MAX_NEW_TOKENS,
+                temperature: LSP_TEMPERATURE,
+                n: Some(self.n_choices.load(Ordering::Relaxed)),
+            },
+            multiline: line_prefix.trim().is_empty(),
+        };
+        Ok((self.get_completions(params).await?, line_prefix))
+    }
+
+    // The file text, the word under the cursor, and the cursor as tree-sitter sees it (column in bytes)
+    async fn get_word_at_position(&self, text_document_position: &TextDocumentPositionParams) -> Option<(String, String, Point)> {
+        let path = text_document_position.text_document.uri.path().to_string();
+        let text = files_in_workspace::get_file_text_from_memory_or_disk(self.gcx.clone(), &path).await.ok()?;
+        let rope = Rope::from_str(&text);
+        let line_n = text_document_position.position.line as usize;
+        if line_n >= rope.len_lines() {
+            return None;
+        }
+        let line_start = rope.line_to_char(line_n);
+        let line: Vec<char> = rope.line(line_n).chars().collect();
+        // LSP counts UTF-16 code units
+        let cursor_char = files_in_workspace::lsp_position_to_char(&rope, &text_document_position.position);
+        let cursor = (cursor_char - line_start).min(line.len());
+        let is_word_char = |c: &char| c.is_alphanumeric() || *c == '_';
+        let start = cursor - line[..cursor].iter().rev().take_while(|c| is_word_char(c)).count();
+        let end = cursor + line[cursor..].iter().take_while(|c| is_word_char(c)).count();
+        if start == end {
+            return None;
+        }
+        let column = rope.char_to_byte(cursor_char) - rope.line_to_byte(line_n);
+        Some((text, line[start..end].iter().collect(), Point::new(line_n, column)))
+    }
+
+    // Declarations the usages near the cursor resolve to, and declarations of the same name in this file:
+    // search_declarations_by_cursor() skips the file it's called for
+    async fn definitions_at_cursor(&self, uri: &Url, text: &String, name: &str, cursor: Point) -> Option<Vec<SymbolInfo>> {
+        let doc = DocumentInfo::from_pathbuf_and_text(&PathBuf::from(uri.path()), text).ok()?;
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let mut ast_module_locked = ast_module.lock().await;
+        let ast = ast_module_locked.as_mut()?;
+        let mut declarations = ast.get_file_symbols(&doc).await.map(|x| x.symbols).unwrap_or_default();
+        match ast.search_declarations_by_cursor(&doc, text, cursor, AST_LSP_TOP_N, true).await {
+            Ok(res) => declarations.extend(res.search_results.into_iter().map(|x| x.symbol_declaration)),
+            Err(e) => info!("LSP definition of {:?}: {}", name, e),
+        }
+        Some(declarations.into_iter()
+            .filter(|decl| decl.name == name)
+            .unique_by(|decl| decl.meta_path.clone())
+            .map(|decl| decl.definition_info)
+            .collect())
+    }
+
+    pub async fn inline_completion(&self, params: InlineCompletionParams) -> Result<InlineCompletionList> {
+        let position = params.text_document_position.position;
+        let (res, _) = self.get_completions_at_position(params.text_document_position).await?;
+        let items = res.choices.iter()
+            .filter(|c| !c.code_completion.is_empty())
+            .map(|c| InlineCompletionItem {
+                insert_text: c.code_completion.clone(),
+                range: Some(Range { start: position, end: position }),
+                command: Some(Command {
+                    title: "Accept completion".to_string(),
+                    command: ACCEPT_COMPLETION_COMMAND.to_string(),
+                    arguments: Some(vec![serde_json::json!({"snippet_telemetry_id": c.snippet_telemetry_id.unwrap_or(res.snippet_telemetry_id as u64)})]),
+                }),
+            })
+            .collect();
+        Ok(InlineCompletionList { items })
+    }
+
     pub async fn accept_snippet(&self, params: SnippetAcceptedParams) -> Result<SuccessRes> {
         let success = snippets_collection::snippet_accepted(self.gcx.clone(), params.snippet_telemetry_id).await;
         Ok(SuccessRes { success })
@@ -194,6 +425,12 @@ impl Backend {
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         info!("LSP client_info {:?}", params.client_info);
+        let n_choices = params.initialization_options.as_ref()
+            .and_then(|x| x.get("completion_choices"))
+            .and_then(|x| x.as_u64())
+            .map(|x| (x as usize).clamp(1, MAX_COMPLETION_CHOICES))
+            .unwrap_or(LSP_N_CHOICES);
+        self.n_choices.store(n_choices, Ordering::Relaxed);
         let mut folders: Vec<PathBuf> = vec![];
         if let Some(nonzero_folders) = params.workspace_folders {
             folders = nonzero_folders.iter().map(|x| PathBuf::from(x.uri.path())).collect();
@@ -222,9 +459,22 @@ impl LanguageServer for Backend {
             }),
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(completion_options),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![ACCEPT_COMPLETION_COMMAND.to_string()],
+                    work_done_progress_options: WorkDoneProgressOptions { work_done_progress: Some(false) },
+                }),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                // LSP 3.18 puts inlineCompletionProvider at the top level, lsp-types under tower-lsp 0.20 has no field
+                // for it. Clients that follow 3.18 don't find it here and use textDocument/completion, which works the
+                // same way. It's for clients that call textDocument/inlineCompletion as a custom method, they can check
+                // experimental capabilities to see if the server has it.
+                experimental: Some(serde_json::json!({"inlineCompletionProvider": true})),
                 ..Default::default()
             },
         })
@@ -247,10 +497,10 @@ impl LanguageServer for Backend {
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        files_in_workspace::on_did_change(
+        files_in_workspace::on_did_change_incremental(
             self.gcx.clone(),
             &params.text_document.uri,
-            &params.content_changes[0].text  // TODO: This text could be just a part of the whole file
+            &params.content_changes,
         ).await
     }
 
@@ -272,39 +522,224 @@ impl LanguageServer for Backend {
 
     async fn shutdown(&self) -> Result<()> {
         let _ = info!("shutdown");
+        forget_lsp_client(self.gcx.clone(), &self.client_id).await;
         Ok(())
     }
 
-    async fn completion(&self, _: CompletionParams) -> Result<Option<CompletionResponse>> {
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         info!("LSP asked for popup completions");
-        Ok(Some(CompletionResponse::Array(vec![])))
+        let position = params.text_document_position.position;
+        let (res, line_prefix) = self.get_completions_at_position(params.text_document_position).await?;
+        // popup items are filtered by the word under cursor, so the edit has to start at the word start
+        let word_prefix: String = line_prefix.chars().rev()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect::<Vec<_>>().into_iter().rev().collect();
+        let word_start = Position {
+            line: position.line,
+            character: position.character.saturating_sub(word_prefix.encode_utf16().count() as u32),
+        };
+        let items = res.choices.iter()
+            .filter(|c| !c.code_completion.is_empty())
+            .map(|c| {
+                let new_text = format!("{}{}", word_prefix, c.code_completion);
+                let label = new_text.lines().next().unwrap_or_default().to_string();
+                CompletionItem {
+                    label: label.clone(),
+                    kind: Some(CompletionItemKind::TEXT),
+                    detail: Some(res.model.clone()),
+                    filter_text: Some(label),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range: Range { start: word_start, end: position },
+                        new_text,
+                    })),
+                    command: Some(Command {
+                        title: "Accept completion".to_string(),
+                        command: ACCEPT_COMPLETION_COMMAND.to_string(),
+                        arguments: Some(vec![serde_json::json!({"snippet_telemetry_id": c.snippet_telemetry_id.unwrap_or(res.snippet_telemetry_id as u64)})]),
+                    }),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        Ok(Some(CompletionResponse::Array(items)))
     }
 
-    async fn did_delete_files(&self, _: DeleteFilesParams) {
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let Some((text, name, cursor)) = self.get_word_at_position(&params.text_document_position_params).await else {
+            return Ok(None);
+        };
+        let uri = &params.text_document_position_params.text_document.uri;
+        let Some(definitions) = self.definitions_at_cursor(uri, &text, &name, cursor).await else {
+            return Ok(None);
+        };
+        let locations = definitions.iter()
+            .filter_map(symbol_info_to_location)
+            .collect::<Vec<_>>();
+        info!("LSP definition of {:?}: {} locations", name, locations.len());
+        Ok(Some(GotoDefinitionResponse::Array(locations)))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let Some((text, name, cursor)) = self.get_word_at_position(&params.text_document_position).await else {
+            return Ok(None);
+        };
+        let uri = &params.text_document_position.text_document.uri;
+        let Ok(doc) = DocumentInfo::from_pathbuf_and_text(&PathBuf::from(uri.path()), &text) else {
+            return Ok(None);
+        };
+        let mut symbol_infos = vec![];
+        if params.context.include_declaration {
+            symbol_infos.extend(self.definitions_at_cursor(uri, &text, &name, cursor).await.unwrap_or_default());
+        }
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        match *ast_module.lock().await {
+            Some(ref mut ast) => {
+                // the usage under the cursor tells the symbol path, "Type::name" is not any "name"
+                let cursor_symbols = match ast.search_references_by_cursor(&doc, &text, cursor, AST_LSP_TOP_N, true).await {
+                    Ok(res) => res.cursor_symbols,
+                    Err(e) => {
+                        info!("LSP references of {:?}: {}", name, e);
+                        vec![]
+                    }
+                };
+                let mut symbol_paths = cursor_symbols.iter()
+                    .filter(|x| x.dist_to_cursor == 0 && x.symbol_path.split("::").any(|part| part == name))
+                    .map(|x| x.symbol_path.clone())
+                    .collect::<Vec<_>>();
+                if symbol_paths.is_empty() {
+                    // the cursor is on a declaration, not a usage
+                    symbol_paths.push(name.clone());
+                }
+                for symbol_path in symbol_paths.iter() {
+                    symbol_infos.extend(ast.search_usage_locations(symbol_path, AST_LSP_TOP_N).await);
+                }
+            }
+            None => return Ok(None),
+        };
+        let locations = symbol_infos.iter()
+            .filter_map(symbol_info_to_location)
+            .collect::<Vec<_>>();
+        info!("LSP references of {:?}: {} locations", name, locations.len());
+        Ok(Some(locations))
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let doc = DocumentInfo { uri: params.text_document.uri, document: None };
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let file_symbols = match *ast_module.lock().await {
+            Some(ref ast) => ast.get_file_symbols(&doc).await,
+            None => return Ok(None),
+        };
+        match file_symbols {
+            Ok(res) => Ok(Some(DocumentSymbolResponse::Nested(document_symbols_tree(&res.symbols)))),
+            Err(e) => {
+                info!("LSP document symbols: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        if params.query.is_empty() {
+            return Ok(Some(vec![]));
+        }
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let search_res = match *ast_module.lock().await {
+            Some(ref ast) => ast.search_declarations_by_symbol_path(params.query.clone(), AST_LSP_TOP_N).await,
+            None => return Ok(None),
+        };
+        let results = search_res.map_err(internal_error)?.search_results;
+        #[allow(deprecated)]
+        let symbols = results.iter()
+            .filter_map(|res| {
+                let decl = &res.symbol_declaration;
+                Some(SymbolInformation {
+                    name: decl.name.clone(),
+                    kind: symbol_kind(&decl.symbol_type),
+                    tags: None,
+                    deprecated: None,
+                    location: symbol_info_to_location(&decl.definition_info)?,
+                    container_name: decl.meta_path
+                        .strip_prefix(&format!("{}::", decl.definition_info.path.display()))
+                        .and_then(|x| x.rsplit_once("::"))
+                        .map(|(container, _)| container.to_string()),
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Some(symbols))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
+        if params.command != ACCEPT_COMPLETION_COMMAND {
+            return Err(Error::method_not_found());
+        }
+        let accepted = params.arguments.into_iter().next()
+            .and_then(|x| serde_json::from_value::<SnippetAcceptedParams>(x).ok());
+        match accepted {
+            Some(accepted) => {
+                let res = self.accept_snippet(accepted).await?;
+                Ok(Some(serde_json::json!(res)))
+            }
+            None => Err(Error::invalid_params("expected {\"snippet_telemetry_id\": ...}")),
+        }
+    }
+
+    async fn did_delete_files(&self, params: DeleteFilesParams) {
         self.client
             .log_message(MessageType::INFO, "{refact-lsp} delete files")
             .await;
+        let paths = params.files.iter()
+            .filter_map(|x| Url::parse(&x.uri).ok())
+            .map(|x| PathBuf::from(x.path()))
+            .collect::<Vec<_>>();
+        files_in_workspace::on_did_delete_files(self.gcx.clone(), &paths).await;
     }
 
-    async fn did_create_files(&self, _: CreateFilesParams) {
+    async fn did_create_files(&self, params: CreateFilesParams) {
         self.client
             .log_message(MessageType::INFO, "{refact-lsp} create files")
             .await;
+        let paths = params.files.iter()
+            .filter_map(|x| Url::parse(&x.uri).ok())
+            .map(|x| PathBuf::from(x.path()))
+            .collect::<Vec<_>>();
+        files_in_workspace::on_did_create_or_modify_files(self.gcx.clone(), &paths).await;
     }
 }
 
+async fn forget_lsp_client(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    client_id: &str,
+) {
+    gcx.read().await.lsp_clients.lock().unwrap().clients.remove(client_id);
+}
+
+// The client id is returned as well, forget the client when the connection is over
 async fn build_lsp_service(
     gcx: Arc<ARwLock<global_context::GlobalContext>>,
-) -> (LspService::<Backend>, ClientSocket) {
-    let (lsp_service, socket) = LspService::build(|client| Backend {
-        gcx,
-        client,
+) -> (LspService::<Backend>, ClientSocket, String) {
+    let lsp_clients = gcx.read().await.lsp_clients.clone();
+    let client_id = {
+        let mut lsp_clients_locked = lsp_clients.lock().unwrap();
+        lsp_clients_locked.next_client_id += 1;
+        format!("lsp-{}", lsp_clients_locked.next_client_id)
+    };
+    let client_id_copy = client_id.clone();
+    let (lsp_service, socket) = LspService::build(|client| {
+        lsp_clients.lock().unwrap().clients.insert(client_id.clone(), client.clone());
+        Backend {
+            gcx,
+            client,
+            client_id,
+            n_choices: AtomicUsize::new(LSP_N_CHOICES),
+        }
     })
         .custom_method("refact/getCompletions", Backend::get_completions)
         .custom_method("refact/acceptCompletion", Backend::accept_snippet)
         .custom_method("refact/test_if_head_tail_equal_return_added_text", Backend::test_if_head_tail_equal_return_added_text)
+        .custom_method("textDocument/inlineCompletion", Backend::inline_completion)
         .finish();
-    (lsp_service, socket)
+    (lsp_service, socket, client_id_copy)
 }
 
 pub async fn spawn_lsp_task(
@@ -324,8 +759,9 @@ pub async fn spawn_lsp_task(
                     Ok((s, addr)) => {
                         info!("LSP new client connection from {}", addr);
                         let (read, write) = tokio::io::split(s);
-                        let (lsp_service, socket) = build_lsp_service(gcx_t.clone()).await;
+                        let (lsp_service, socket, client_id) = build_lsp_service(gcx_t.clone()).await;
                         tower_lsp::Server::new(read, write, socket).serve(lsp_service).await;
+                        forget_lsp_client(gcx_t.clone(), &client_id).await;
                     }
                     Err(e) => {
                         error!("Error accepting client connection: {}", e);
@@ -340,8 +776,9 @@ pub async fn spawn_lsp_task(
         return Some(tokio::spawn(async move {
             let stdin = tokio::io::stdin();
             let stdout = tokio::io::stdout();
-            let (lsp_service, socket) = build_lsp_service(gcx_t.clone()).await;
+            let (lsp_service, socket, client_id) = build_lsp_service(gcx_t.clone()).await;
             tower_lsp::Server::new(stdin, stdout, socket).serve(lsp_service).await;
+            forget_lsp_client(gcx_t.clone(), &client_id).await;
             info!("LSP loop exit");
             gcx_t.write().await.ask_shutdown_sender.lock().unwrap().send(format!("going-down-because-lsp-exited")).unwrap();
         }));
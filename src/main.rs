@@ -1,7 +1,8 @@
 use std::io::Write;
+use std::path::PathBuf;
 
 use tokio::task::JoinHandle;
-use tracing::{info, Level};
+use tracing::{error, info, Level};
 use tracing_appender;
 
 use crate::background_tasks::start_background_tasks;
@@ -10,15 +11,22 @@ use crate::telemetry::{basic_transmit, snippets_transmit};
 
 mod global_context;
 mod caps;
+mod caps_overlay;
 mod call_validation;
 mod scratchpads;
 mod scratchpad_abstract;
+mod forward_ndjson;
+mod forward_to_anthropic_endpoint;
 mod forward_to_hf_endpoint;
+mod forward_to_llama_cpp_endpoint;
+mod forward_to_ollama_endpoint;
 mod forward_to_openai_endpoint;
 mod cached_tokenizers;
 mod restream;
 mod custom_error;
 mod completion_cache;
+mod completion_cache_disk;
+mod completion_in_flight;
 mod telemetry;
 mod lsp;
 mod http;
@@ -70,6 +78,12 @@ async fn main() {
             info!("{:>20} {}", k, v);
         }
     }
+    if !cmdline.import_tokenizers.is_empty() {
+        match cached_tokenizers::import_tokenizer_bundle(&PathBuf::from(&cmdline.import_tokenizers), &cache_dir).await {
+            Ok(n) => info!("imported {} tokenizers from {}", n, cmdline.import_tokenizers),
+            Err(e) => error!("failed to import tokenizers from {}: {}", cmdline.import_tokenizers, e),
+        }
+    }
     files_in_workspace::enqueue_all_files_from_workspace_folders(gcx.clone()).await;
     let mut background_tasks = start_background_tasks(gcx.clone()).await;
     // vector db will spontaneously start if the downloaded caps and command line parameters are right
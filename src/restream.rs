@@ -1,16 +1,27 @@
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::RwLock as StdRwLock;
+use std::time::Duration;
 
 use async_stream::stream;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use hyper::{Body, Response, StatusCode};
-use reqwest_eventsource::Event;
+use reqwest_eventsource::{Event, EventSource};
 use serde_json::json;
 use tokio::sync::RwLock as ARwLock;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::call_validation::SamplingParameters;
+use crate::caps;
+use crate::caps::{EndpointRetry, ModelEndpoint};
+use crate::completion_in_flight::InFlightGuard;
 use crate::custom_error::ScratchError;
+use crate::forward_ndjson::NdjsonStream;
+use crate::forward_to_anthropic_endpoint;
 use crate::forward_to_hf_endpoint;
+use crate::forward_to_llama_cpp_endpoint;
+use crate::forward_to_ollama_endpoint;
 use crate::forward_to_openai_endpoint;
 use crate::global_context::GlobalContext;
 use crate::nicer_logs;
@@ -28,36 +39,32 @@ pub async fn scratchpad_interaction_not_stream(
     parameters: &SamplingParameters,
 ) -> Result<Response<Body>, ScratchError> {
     let t2 = std::time::SystemTime::now();
-    let (endpoint_style, endpoint_template, endpoint_chat_passthrough, tele_storage, slowdown_arc) = {
+    let (endpoints_maybe, endpoint_retry, tele_storage, slowdown_arc) = {
         let cx = global_context.write().await;
         let caps = cx.caps.clone().unwrap();
         let caps_locked = caps.read().unwrap();
-        (caps_locked.endpoint_style.clone(), caps_locked.endpoint_template.clone(), caps_locked.endpoint_chat_passthrough.clone(), cx.telemetry.clone(), cx.http_client_slowdown.clone())
+        (
+            caps::endpoints_with_fallbacks(&caps_locked, &model_name, scope.starts_with("chat"), &bearer),
+            caps_locked.endpoint_retry.clone(),
+            cx.telemetry.clone(),
+            cx.http_client_slowdown.clone(),
+        )
     };
+    let endpoints = endpoints_maybe.map_err(|e|
+        ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e)
+    )?;
     let mut save_url: String = String::new();
     let _ = slowdown_arc.acquire().await;
-    let model_says = if endpoint_style == "hf" {
-        forward_to_hf_endpoint::forward_to_hf_style_endpoint(
-            &mut save_url,
-            bearer.clone(),
-            &model_name,
-            &prompt,
-            &client,
-            &endpoint_template,
-            &parameters,
-        ).await
-    } else {
-        forward_to_openai_endpoint::forward_to_openai_style_endpoint(
-            &mut save_url,
-            bearer.clone(),
-            &model_name,
-            &prompt,
-            &client,
-            &endpoint_template,
-            &endpoint_chat_passthrough,
-            &parameters,
-        ).await
-    }.map_err(|e| {
+    let (_, model_says) = _forward_not_stream_with_retries(
+        &mut save_url,
+        &endpoints,
+        &endpoint_retry,
+        &prompt,
+        &client,
+        &parameters,
+        &scope,
+        &tele_storage,
+    ).await.map_err(|e| {
         tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
                 save_url.clone(),
                 scope.clone(),
@@ -82,7 +89,8 @@ pub async fn scratchpad_interaction_not_stream(
                 x.get("generated_text").unwrap().as_str().unwrap().to_string()
             }).collect::<Vec<_>>();
         let stopped = vec![false; choices.len()];
-        scratchpad_result = scratchpad.response_n_choices(choices, stopped);
+        let logprobs = vec![None; choices.len()];
+        scratchpad_result = scratchpad.response_n_choices(choices, stopped, logprobs);
 
     } else if let Some(oai_choices) = model_says.get("choices") {
         let choices = oai_choices.as_array().unwrap().iter()
@@ -93,7 +101,19 @@ pub async fn scratchpad_interaction_not_stream(
             .map(|x| {
                 x.get("finish_reason").unwrap_or(&json!("")).as_str().unwrap().to_string().starts_with("stop")
             }).collect::<Vec<_>>();
-        scratchpad_result = scratchpad.response_n_choices(choices, stopped);
+        let logprobs = oai_choices.as_array().unwrap().iter()
+            .map(|x| _mean_logprob(x))
+            .collect::<Vec<_>>();
+        scratchpad_result = scratchpad.response_n_choices(choices, stopped, logprobs);
+
+    } else if let Some((text, stopped)) = forward_to_anthropic_endpoint::anthropic_response_text(&model_says) {
+        scratchpad_result = scratchpad.response_n_choices(vec![text], vec![stopped], vec![None]);
+
+    } else if let Some((text, stopped)) = forward_to_ollama_endpoint::ollama_response_text(&model_says) {
+        scratchpad_result = scratchpad.response_n_choices(vec![text], vec![stopped], vec![None]);
+
+    } else if let Some((text, stopped)) = forward_to_llama_cpp_endpoint::llama_cpp_response_text(&model_says) {
+        scratchpad_result = scratchpad.response_n_choices(vec![text], vec![stopped], vec![None]);
 
     } else if let Some(err) = model_says.get("error") {
         return Err(ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR,
@@ -142,15 +162,30 @@ pub async fn scratchpad_interaction_stream(
     client: reqwest::Client,
     bearer: String,
     parameters: SamplingParameters,
+    mut in_flight: Option<InFlightGuard>,  // code completion only, it stops the stream when superseded
 ) -> Result<Response<Body>, ScratchError> {
     let t1 = std::time::SystemTime::now();
+    let (endpoints_maybe, endpoint_retry) = {
+        let cx = global_context.read().await;
+        let caps = cx.caps.clone().unwrap();
+        let caps_locked = caps.read().unwrap();
+        (
+            caps::endpoints_with_fallbacks(&caps_locked, &model_name, scope.starts_with("chat"), &bearer),
+            caps_locked.endpoint_retry.clone(),
+        )
+    };
+    let endpoints = match endpoints_maybe {
+        Ok(x) => x,
+        Err(e) => {
+            _finish(&mut in_flight);
+            return Err(ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e));
+        }
+    };
     let evstream = stream! {
         let scratch: &mut Box<dyn ScratchpadAbstract> = &mut scratchpad;
-        let (endpoint_style, endpoint_template, endpoint_chat_passthrough, tele_storage, slowdown_arc) = {
+        let (tele_storage, slowdown_arc) = {
             let cx = global_context.write().await;
-            let caps = cx.caps.clone().unwrap();
-            let caps_locked = caps.read().unwrap();
-            (caps_locked.endpoint_style.clone(), caps_locked.endpoint_template.clone(), caps_locked.endpoint_chat_passthrough.clone(), cx.telemetry.clone(), cx.http_client_slowdown.clone())
+            (cx.telemetry.clone(), cx.http_client_slowdown.clone())
         };
         let mut save_url: String = String::new();
         let _ = slowdown_arc.acquire().await;
@@ -171,31 +206,26 @@ pub async fn scratchpad_interaction_stream(
                 }
             }
 
-            let event_source_maybe = if endpoint_style == "hf" {
-                forward_to_hf_endpoint::forward_to_hf_style_endpoint_streaming(
-                    &mut save_url,
-                    bearer.clone(),
-                    &model_name,
-                    &prompt,
-                    &client,
-                    &endpoint_template,
-                    &parameters,
-                ).await
-            } else {
-                forward_to_openai_endpoint::forward_to_openai_style_endpoint_streaming(
-                    &mut save_url,
-                    bearer.clone(),
-                    &model_name,
-                    &prompt,
-                    &client,
-                    &endpoint_template,
-                    &endpoint_chat_passthrough,
-                    &parameters,
-                ).await
-            };
-            let mut event_source = match event_source_maybe {
-                Ok(event_source) => event_source,
-                Err(e) => {
+            let event_stream_maybe = _unless_superseded(&mut in_flight, _forward_streaming_with_retries(
+                &mut save_url,
+                &endpoints,
+                &endpoint_retry,
+                &prompt,
+                &client,
+                &parameters,
+                &scope,
+                &tele_storage,
+            )).await;
+            let mut event_stream = match event_stream_maybe {
+                None => {
+                    yield Result::<_, String>::Ok(_superseded_str());
+                    return;
+                },
+                Some(Ok((model_used, event_stream))) => {
+                    model_name = model_used;
+                    event_stream
+                },
+                Some(Err(e)) => {
                     let e_str = format!("forward_to_endpoint: {:?}", e);
                     tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
                         save_url.clone(),
@@ -213,14 +243,26 @@ pub async fn scratchpad_interaction_stream(
             let mut problem_reported = false;
             let mut was_correct_output_even_if_error = false;
             // let mut test_countdown = 250;
-            while let Some(event) = event_source.next().await {
+            loop {
+                let event = match _unless_superseded(&mut in_flight, event_stream.next()).await {
+                    Some(Some(event)) => event,
+                    Some(None) => break,
+                    None => {
+                        yield Result::<_, String>::Ok(_superseded_str());
+                        return;
+                    }
+                };
                 match event {
-                    Ok(Event::Open) => {},
-                    Ok(Event::Message(message)) => {
-                        // info!("Message: {:#?}", message);
-                        if message.data.starts_with("[DONE]") {
+                    Ok((event_type, data)) => {
+                        if data.starts_with("[DONE]") {
                             break;
                         }
+                        // anthropic style: only content_block_delta and message_delta carry anything useful
+                        match event_type.as_str() {
+                            "message_start" | "content_block_start" | "content_block_stop" | "ping" => continue,
+                            "message_stop" => break,
+                            _ => {}
+                        }
                         // test_countdown -= 1;
                         // if test_countdown == 0 {
                         //     error!("test_countdown!");
@@ -229,7 +271,14 @@ pub async fn scratchpad_interaction_stream(
                         //     problem_reported = true;
                         //     break;
                         // }
-                        let json = serde_json::from_str::<serde_json::Value>(&message.data).unwrap();
+                        let json = match serde_json::from_str::<serde_json::Value>(&data) {
+                            Ok(json) => json,
+                            Err(e) => {
+                                // keep-alive comments, stray log lines from a local server
+                                warn!("{}: skipping a line that is not json ({}): {:?}", save_url, e, crate::nicer_logs::first_n_chars(&data, 60));
+                                continue;
+                            }
+                        };
                         crate::global_context::look_for_piggyback_fields(global_context.clone(), &json).await;
                         let value_maybe = _push_streaming_json_into_scratchpad(
                             scratch,
@@ -262,7 +311,7 @@ pub async fn scratchpad_interaction_stream(
                             // "restream error: Stream ended"
                             break;
                         }
-                        error!("restream error: {}\n{:?}", err, err);
+                        error!("restream error: {}", err);
                         let problem_str = format!("restream error: {}", err);
                         {
                             tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
@@ -274,12 +323,12 @@ pub async fn scratchpad_interaction_stream(
                         }
                         yield Result::<_, String>::Ok(serde_json::to_string(&json!({"detail": problem_str})).unwrap());
                         problem_reported = true;
-                        event_source.close();
                         break;
                     },
                 }
             }
             if problem_reported {
+                _finish(&mut in_flight);
                 return;
             } else if !finished {
                 let mut value: serde_json::Value;
@@ -294,6 +343,7 @@ pub async fn scratchpad_interaction_stream(
         }
         info!("yield: [DONE]");
         yield Result::<_, String>::Ok("data: [DONE]\n\n".to_string());
+        _finish(&mut in_flight);
         tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
             save_url.clone(),
             scope.clone(),
@@ -309,6 +359,325 @@ pub async fn scratchpad_interaction_stream(
     return Ok(response);
 }
 
+async fn _unless_superseded<T>(in_flight: &mut Option<InFlightGuard>, f: impl Future<Output = T>) -> Option<T> {
+    match in_flight {
+        Some(in_flight) => in_flight.run_unless_superseded(f).await,
+        None => Some(f.await),
+    }
+}
+
+fn _finish(in_flight: &mut Option<InFlightGuard>) {
+    if let Some(in_flight) = in_flight {
+        in_flight.finish();
+    }
+}
+
+fn _superseded_str() -> String {
+    info!("superseded, stream stopped");
+    format!("data: {}\n\n", serde_json::to_string(&json!({"detail": "superseded by a newer completion request for the same file"})).unwrap())
+}
+
+// OpenAI-style {"logprobs": {"token_logprobs": [null, -0.1, ...]}}, the first token can be null
+fn _mean_logprob(choice: &serde_json::Value) -> Option<f32> {
+    let token_logprobs = choice.get("logprobs")?.get("token_logprobs")?.as_array()?;
+    let values = token_logprobs.iter().filter_map(|x| x.as_f64()).collect::<Vec<_>>();
+    if values.is_empty() {
+        return None;
+    }
+    Some((values.iter().sum::<f64>() / values.len() as f64) as f32)
+}
+
+async fn _forward_not_stream(
+    save_url: &mut String,
+    model_name: &String,
+    endpoint: &ModelEndpoint,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+) -> Result<serde_json::Value, String> {
+    if endpoint.endpoint_style == "hf" {
+        forward_to_hf_endpoint::forward_to_hf_style_endpoint(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            parameters,
+        ).await
+    } else if endpoint.endpoint_style == "ollama" {
+        forward_to_ollama_endpoint::forward_to_ollama_style_endpoint(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            parameters,
+        ).await
+    } else if endpoint.endpoint_style == "llama_cpp" {
+        forward_to_llama_cpp_endpoint::forward_to_llama_cpp_style_endpoint(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            parameters,
+        ).await
+    } else if endpoint.endpoint_style == "anthropic" {
+        forward_to_anthropic_endpoint::forward_to_anthropic_style_endpoint(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            &endpoint.endpoint_chat_passthrough,
+            parameters,
+        ).await
+    } else {
+        forward_to_openai_endpoint::forward_to_openai_style_endpoint(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            &endpoint.endpoint_chat_passthrough,
+            parameters,
+        ).await
+    }
+}
+
+async fn _forward_streaming(
+    save_url: &mut String,
+    model_name: &String,
+    endpoint: &ModelEndpoint,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+) -> Result<EventStream, String> {
+    if endpoint.endpoint_style == "hf" {
+        forward_to_hf_endpoint::forward_to_hf_style_endpoint_streaming(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            parameters,
+        ).await.map(_event_source_to_stream)
+    } else if endpoint.endpoint_style == "ollama" {
+        forward_to_ollama_endpoint::forward_to_ollama_style_endpoint_streaming(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            parameters,
+        ).await.map(_ndjson_to_stream)
+    } else if endpoint.endpoint_style == "llama_cpp" {
+        forward_to_llama_cpp_endpoint::forward_to_llama_cpp_style_endpoint_streaming(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            parameters,
+        ).await.map(_ndjson_to_stream)
+    } else if endpoint.endpoint_style == "anthropic" {
+        forward_to_anthropic_endpoint::forward_to_anthropic_style_endpoint_streaming(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            &endpoint.endpoint_chat_passthrough,
+            parameters,
+        ).await.map(_event_source_to_stream)
+    } else {
+        forward_to_openai_endpoint::forward_to_openai_style_endpoint_streaming(
+            save_url,
+            endpoint.api_key.clone(),
+            model_name,
+            prompt,
+            client,
+            &endpoint.endpoint_template,
+            &endpoint.endpoint_chat_passthrough,
+            parameters,
+        ).await.map(_event_source_to_stream)
+    }
+}
+
+// Waits for the first event, so a bad status or a dead server shows up here and not in the middle of restreaming
+async fn _connect_streaming(
+    save_url: &mut String,
+    model_name: &String,
+    endpoint: &ModelEndpoint,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+) -> Result<EventStream, String> {
+    let mut event_stream = _forward_streaming(save_url, model_name, endpoint, prompt, client, parameters).await?;
+    match event_stream.next().await {
+        Some(Err(e)) => Err(e),
+        Some(Ok(first)) => Ok(Box::pin(futures::stream::iter(vec![Ok(first)]).chain(event_stream))),
+        None => Ok(event_stream),
+    }
+}
+
+async fn _with_timeout<T>(
+    timeout_ms: u64,
+    fut: impl Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    if timeout_ms == 0 {
+        return fut.await;
+    }
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), fut).await {
+        Ok(res) => res,
+        Err(_) => Err(format!("no response in {}ms", timeout_ms)),
+    }
+}
+
+fn _backoff(endpoint_retry: &EndpointRetry, retry_n: usize) -> Duration {
+    let ms = endpoint_retry.backoff_ms.saturating_mul(1 << retry_n.min(16)).min(endpoint_retry.backoff_max_ms);
+    Duration::from_millis(ms)
+}
+
+// Forwarders say "status=404", EventSource says "Invalid status code: 404 Not Found"
+fn _status_code(err: &str) -> Option<u16> {
+    for marker in ["status=", "status code: "] {
+        if let Some(pos) = err.find(marker) {
+            let digits = err[pos + marker.len()..].chars().take_while(|c| c.is_ascii_digit()).collect::<String>();
+            if let Ok(status) = digits.parse::<u16>() {
+                return Some(status);
+            }
+        }
+    }
+    None
+}
+
+// Client errors (no such model, bad key) won't go away by retrying the same endpoint, but a fallback might help.
+// Timeouts, rate limits and server errors are retried, so are errors without a status (connection refused).
+fn _worth_retrying(err: &str) -> bool {
+    match _status_code(err) {
+        Some(408) | Some(429) => true,
+        Some(status) => status >= 500,
+        None => true,
+    }
+}
+
+// Records the failure and tells if there's another attempt to make
+fn _report_failed_attempt(
+    save_url: &String,
+    scope: &String,
+    err: &String,
+    can_retry: bool,
+    can_failover: bool,
+    tele_storage: &Arc<StdRwLock<telemetry_structs::Storage>>,
+) -> bool {
+    let what = if can_retry { "retry" } else if can_failover { "failover" } else { return false; };
+    warn!("{} failed, {}: {}", save_url, what, err);
+    tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
+        save_url.clone(),
+        scope.clone(),
+        false,
+        format!("{}: {}", what, err),
+    ));
+    true
+}
+
+async fn _forward_not_stream_with_retries(
+    save_url: &mut String,
+    endpoints: &Vec<(String, ModelEndpoint)>,
+    endpoint_retry: &EndpointRetry,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+    scope: &String,
+    tele_storage: &Arc<StdRwLock<telemetry_structs::Storage>>,
+) -> Result<(String, serde_json::Value), String> {
+    let mut last_err = String::new();
+    for (endpoint_n, (model_name, endpoint)) in endpoints.iter().enumerate() {
+        for retry_n in 0..=endpoint_retry.max_retries {
+            if retry_n > 0 {
+                tokio::time::sleep(_backoff(endpoint_retry, retry_n - 1)).await;
+            }
+            // the body is a part of the response here, a long answer takes long, so the first response timeout doesn't apply
+            let res = _with_timeout(
+                endpoint_retry.total_timeout_ms,
+                _forward_not_stream(save_url, model_name, endpoint, prompt, client, parameters),
+            ).await;
+            match res {
+                Ok(model_says) => return Ok((model_name.clone(), model_says)),
+                Err(e) => last_err = e,
+            }
+            let can_retry = retry_n < endpoint_retry.max_retries && _worth_retrying(&last_err);
+            let can_failover = endpoint_n + 1 < endpoints.len();
+            if !_report_failed_attempt(save_url, scope, &last_err, can_retry, can_failover, tele_storage) || !can_retry {
+                break;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+async fn _forward_streaming_with_retries(
+    save_url: &mut String,
+    endpoints: &Vec<(String, ModelEndpoint)>,
+    endpoint_retry: &EndpointRetry,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+    scope: &String,
+    tele_storage: &Arc<StdRwLock<telemetry_structs::Storage>>,
+) -> Result<(String, EventStream), String> {
+    let mut last_err = String::new();
+    for (endpoint_n, (model_name, endpoint)) in endpoints.iter().enumerate() {
+        for retry_n in 0..=endpoint_retry.max_retries {
+            if retry_n > 0 {
+                tokio::time::sleep(_backoff(endpoint_retry, retry_n - 1)).await;
+            }
+            let res = _with_timeout(
+                endpoint_retry.first_response_timeout_ms,
+                _connect_streaming(save_url, model_name, endpoint, prompt, client, parameters),
+            ).await;
+            match res {
+                Ok(event_stream) => return Ok((model_name.clone(), event_stream)),
+                Err(e) => last_err = e,
+            }
+            let can_retry = retry_n < endpoint_retry.max_retries && _worth_retrying(&last_err);
+            let can_failover = endpoint_n + 1 < endpoints.len();
+            if !_report_failed_attempt(save_url, scope, &last_err, can_retry, can_failover, tele_storage) || !can_retry {
+                break;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+// (event type, data), event type is "message" unless the server says otherwise
+type EventStream = Pin<Box<dyn Stream<Item = Result<(String, String), String>> + Send>>;
+
+// Dropping the stream closes the connection, so EventSource won't try to reconnect after an error
+fn _event_source_to_stream(event_source: EventSource) -> EventStream {
+    Box::pin(event_source.filter_map(|event| async move {
+        match event {
+            Ok(Event::Open) => None,
+            Ok(Event::Message(message)) => Some(Ok((message.event, message.data))),
+            Err(err) => Some(Err(format!("{}", err))),
+        }
+    }))
+}
+
+fn _ndjson_to_stream(lines: NdjsonStream) -> EventStream {
+    Box::pin(lines.map(|line| line.map(|data| ("message".to_string(), data))))
+}
+
 fn _push_streaming_json_into_scratchpad(
     scratch: &mut Box<dyn ScratchpadAbstract>,
     json: &serde_json::Value,
@@ -344,6 +713,37 @@ fn _push_streaming_json_into_scratchpad(
         }
         value["model"] = json!(model_name.clone());
         Ok(value)
+    } else if let Some(delta) = json.get("delta") { // anthropic style
+        let mut value: serde_json::Value;
+        let stop_reason = delta.get("stop_reason").unwrap_or(&json!("")).as_str().unwrap_or("").to_string();
+        let stop_toks = forward_to_anthropic_endpoint::anthropic_stop_reason_is_stop(&stop_reason);
+        let stop_length = !stop_reason.is_empty() && !stop_toks;
+        let text = delta.get("text").unwrap_or(&json!("")).as_str().unwrap_or("").to_string();
+        (value, *finished) = scratch.response_streaming(text, stop_toks, stop_length)?;
+        *was_correct_output_even_if_error |= !stop_reason.is_empty();
+        value["model"] = json!(model_name.clone());
+        Ok(value)
+    } else if let Some(response) = json.get("response") { // ollama style
+        let mut value: serde_json::Value;
+        let text = response.as_str().unwrap_or("").to_string();
+        let done = json.get("done").unwrap_or(&json!(false)).as_bool().unwrap_or(false);
+        let done_reason = json.get("done_reason").unwrap_or(&json!("")).as_str().unwrap_or("").to_string();
+        let stop_length = done && done_reason == "length";
+        let stop_toks = done && !stop_length;
+        (value, *finished) = scratch.response_streaming(text, stop_toks, stop_length)?;
+        *was_correct_output_even_if_error |= done;
+        value["model"] = json!(model_name.clone());
+        Ok(value)
+    } else if let (Some(content), Some(stop)) = (json.get("content"), json.get("stop")) { // llama.cpp style
+        let mut value: serde_json::Value;
+        let text = content.as_str().unwrap_or("").to_string();
+        let stop = stop.as_bool().unwrap_or(false);
+        let stop_length = stop && json.get("stopped_limit").unwrap_or(&json!(false)).as_bool().unwrap_or(false);
+        let stop_toks = stop && !stop_length;
+        (value, *finished) = scratch.response_streaming(text, stop_toks, stop_length)?;
+        *was_correct_output_even_if_error |= stop;
+        value["model"] = json!(model_name.clone());
+        Ok(value)
     } else if let Some(err) = json.get("error") {
         Err(format!("{}", err))
     } else if let Some(msg) = json.get("human_readable_message") {
@@ -381,3 +781,129 @@ pub async fn cached_stream(
        .unwrap();
     return Ok(response);
 }
+
+#[cfg(test)]
+mod tests {
+    use mockito::mock;
+
+    use super::*;
+
+    fn endpoint(style: &str, template: String) -> ModelEndpoint {
+        ModelEndpoint {
+            endpoint_style: style.to_string(),
+            endpoint_template: template,
+            endpoint_chat_passthrough: "".to_string(),
+            api_key: "".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_then_failover() {
+        let primary = mock("POST", "/primary")
+            .with_status(503)
+            .with_body("overloaded")
+            .expect(2)
+            .create();
+        let fallback = mock("POST", "/fallback")
+            .with_status(200)
+            .with_body(r#"{"choices": [{"text": "hello", "finish_reason": "stop"}]}"#)
+            .expect(1)
+            .create();
+        let endpoints = vec![
+            ("model-a".to_string(), endpoint("openai", format!("{}/primary", mockito::server_url()))),
+            ("model-a".to_string(), endpoint("openai", format!("{}/fallback", mockito::server_url()))),
+        ];
+        let endpoint_retry = EndpointRetry { max_retries: 1, backoff_ms: 1, backoff_max_ms: 1, first_response_timeout_ms: 0, total_timeout_ms: 0 };
+        let tele_storage = Arc::new(StdRwLock::new(telemetry_structs::Storage::new()));
+        let mut save_url = String::new();
+        let (model_used, model_says) = _forward_not_stream_with_retries(
+            &mut save_url,
+            &endpoints,
+            &endpoint_retry,
+            "def hello",
+            &reqwest::Client::new(),
+            &SamplingParameters::default(),
+            &"completion".to_string(),
+            &tele_storage,
+        ).await.unwrap();
+        primary.assert();
+        fallback.assert();
+        assert_eq!(model_used, "model-a");
+        assert_eq!(model_says["choices"][0]["text"], json!("hello"));
+        assert_eq!(save_url, format!("{}/fallback", mockito::server_url()));
+        let failures = tele_storage.read().unwrap().tele_net.iter()
+            .map(|x| x.error_message.split(':').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(failures, vec!["retry", "failover"]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_body_is_not_cut_off() {
+        let slow = mock("POST", "/slow")
+            .with_status(200)
+            .with_body_from_fn(|w| {
+                w.write_all(br#"{"choices": [{"text": "hel"#)?;
+                w.flush()?;
+                std::thread::sleep(Duration::from_millis(300));
+                w.write_all(br#"lo", "finish_reason": "stop"}]}"#)
+            })
+            .expect(1)
+            .create();
+        let endpoints = vec![
+            ("model-a".to_string(), endpoint("openai", format!("{}/slow", mockito::server_url()))),
+        ];
+        let endpoint_retry = EndpointRetry { max_retries: 0, first_response_timeout_ms: 100, ..EndpointRetry::default() };
+        let tele_storage = Arc::new(StdRwLock::new(telemetry_structs::Storage::new()));
+        let mut save_url = String::new();
+        let (_, model_says) = _forward_not_stream_with_retries(
+            &mut save_url,
+            &endpoints,
+            &endpoint_retry,
+            "def hello",
+            &reqwest::Client::new(),
+            &SamplingParameters::default(),
+            &"completion".to_string(),
+            &tele_storage,
+        ).await.unwrap();
+        slow.assert();
+        assert_eq!(model_says["choices"][0]["text"], json!("hello"));
+        assert!(tele_storage.read().unwrap().tele_net.is_empty());
+    }
+
+    #[test]
+    fn test_worth_retrying() {
+        assert!(!_worth_retrying("http://x/v1/completions status=404 text not found"));
+        assert!(!_worth_retrying("Invalid status code: 401 Unauthorized"));
+        assert!(_worth_retrying("http://x/v1/completions status=429 text slow down"));
+        assert!(_worth_retrying("Invalid status code: 408 Request Timeout"));
+        assert!(_worth_retrying("http://x/v1/completions status=503 text overloaded"));
+        assert!(_worth_retrying("error sending request: connection refused"));
+    }
+
+    #[tokio::test]
+    async fn test_client_error_is_not_retried() {
+        let primary = mock("POST", "/no-such-model")
+            .with_status(404)
+            .with_body("not found")
+            .expect(1)
+            .create();
+        let endpoints = vec![
+            ("model-a".to_string(), endpoint("hf", format!("{}/no-such-model", mockito::server_url()))),
+        ];
+        let tele_storage = Arc::new(StdRwLock::new(telemetry_structs::Storage::new()));
+        let mut save_url = String::new();
+        let err = _forward_not_stream_with_retries(
+            &mut save_url,
+            &endpoints,
+            &EndpointRetry::default(),
+            "def hello",
+            &reqwest::Client::new(),
+            &SamplingParameters::default(),
+            &"completion".to_string(),
+            &tele_storage,
+        ).await.err().unwrap();
+        primary.assert();
+        assert!(err.contains("status=404"));
+        assert!(tele_storage.read().unwrap().tele_net.is_empty());
+    }
+}
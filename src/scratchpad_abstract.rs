@@ -24,6 +24,7 @@ pub trait ScratchpadAbstract: Send {
         &mut self,
         choices: Vec<String>,
         stopped: Vec<bool>,
+        logprobs: Vec<Option<f32>>,  // mean per choice, if the endpoint returns them
     ) -> Result<serde_json::Value, String>;
 
     fn response_streaming(   // Only 1 choice, but streaming. Returns delta the user should see, and finished flag
@@ -61,6 +62,11 @@ impl HasTokenizerAndEot {
         Ok(tokens.len() as i32)
     }
 
+    // Fallback tokenizer, see cached_tokenizers: counts are estimates, special tokens are not known
+    pub fn is_approximate(&self) -> bool {
+        crate::cached_tokenizers::is_approximate_tokenizer(&self.tokenizer.read().unwrap())
+    }
+
     pub fn assert_one_token(
         &self,
         text: &str
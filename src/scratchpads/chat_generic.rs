@@ -138,6 +138,7 @@ impl ScratchpadAbstract for GenericChatScratchpad {
         &mut self,
         choices: Vec<String>,
         stopped: Vec<bool>,
+        _logprobs: Vec<Option<f32>>,
     ) -> Result<serde_json::Value, String> {
         self.dd.response_n_choices(choices, stopped)
     }
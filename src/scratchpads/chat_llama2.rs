@@ -125,6 +125,7 @@ impl ScratchpadAbstract for ChatLlama2 {
         &mut self,
         choices: Vec<String>,
         stopped: Vec<bool>,
+        _logprobs: Vec<Option<f32>>,
     ) -> Result<serde_json::Value, String> {
         self.dd.response_n_choices(choices, stopped)
     }
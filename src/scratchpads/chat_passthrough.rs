@@ -104,6 +104,7 @@ impl ScratchpadAbstract for ChatPassthrough {
         &mut self,
         _choices: Vec<String>,
         _stopped: Vec<bool>,
+        _logprobs: Vec<Option<f32>>,
     ) -> Result<serde_json::Value, String> {
         unimplemented!()
     }
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::call_validation::ContextFile;
+
+const CHUNK_LINES: usize = 20;
+const MIN_SIMILARITY: f32 = 0.05;
+const MAX_FILE_CHARS: usize = 500_000;
+
+
+lazy_static! {
+    static ref WORD_RE: Regex = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+}
+
+fn _words(text: &str) -> HashSet<&str> {
+    WORD_RE.find_iter(text).map(|m| m.as_str()).collect()
+}
+
+pub fn jaccard(a: &HashSet<&str>, b: &HashSet<&str>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+// Other files the user works with, split into chunks of CHUNK_LINES, the chunks most similar to the text
+// around the cursor go first. Same idea as "neighboring tabs" in other assistants.
+pub fn most_similar_chunks(
+    cursor_window: &str,
+    files: &Vec<(String, String)>,
+    top_n: usize,
+) -> Vec<ContextFile> {
+    let window_words = _words(cursor_window);
+    if window_words.is_empty() {
+        return vec![];
+    }
+    let mut scored: Vec<ContextFile> = vec![];
+    for (file_name, text) in files {
+        if text.len() > MAX_FILE_CHARS {
+            continue;
+        }
+        let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+        for (chunk_idx, chunk) in lines.chunks(CHUNK_LINES).enumerate() {
+            let chunk_text = chunk.concat();
+            let score = jaccard(&window_words, &_words(&chunk_text));
+            if score < MIN_SIMILARITY {
+                continue;
+            }
+            scored.push(ContextFile {
+                file_name: file_name.clone(),
+                file_content: chunk_text,
+                line1: chunk_idx * CHUNK_LINES + 1,
+                line2: chunk_idx * CHUNK_LINES + chunk.len(),
+                usefulness: 100.0 * score,
+            });
+        }
+    }
+    scored.sort_by(|a, b| b.usefulness.partial_cmp(&a.usefulness).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(top_n);
+    scored
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_similar_chunks() {
+        let mut other = String::new();
+        for i in 0..CHUNK_LINES {
+            other.push_str(&format!("unrelated_{} = {}\n", i, i));
+        }
+        other.push_str("def compute_total(orders, discount):\n    return sum(o.price for o in orders) * discount\n");
+        let files = vec![
+            ("other.py".to_string(), other),
+            ("empty.py".to_string(), "".to_string()),
+        ];
+        let chunks = most_similar_chunks("total = compute_total(orders, discount", &files, 3);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].file_name, "other.py");
+        assert_eq!((chunks[0].line1, chunks[0].line2), (CHUNK_LINES + 1, CHUNK_LINES + 2));
+        assert!(chunks[0].file_content.starts_with("def compute_total"));
+
+        assert!(most_similar_chunks("", &files, 3).is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use ropey::Rope;
+use tree_sitter::Parser;
+
+use crate::ast::treesitter::parsers::get_parser_by_filename;
+
+const MAX_CANDIDATES: usize = 32;
+
+
+fn _count_errors(parser: &mut Parser, code: &str) -> Option<usize> {
+    let tree = parser.parse(code, None)?;
+    let mut count = 0;
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            count += 1;
+        }
+        // subtrees without errors are not interesting
+        if node.has_error() && !node.is_error() && cursor.goto_first_child() {
+            continue;
+        }
+        loop {
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                return Some(count);
+            }
+        }
+    }
+}
+
+// Trailing lines of the completion that repeat the lines after the cursor, typically a closing bracket
+fn _drop_suffix_duplicates(completion: &str, suffix: &str) -> String {
+    let lines = completion.split('\n').collect::<Vec<_>>();
+    // indent is a part of the line, a closing bracket of a nested block is not the same as the one after the cursor
+    let suffix_lines = suffix.split('\n')
+        .skip(1)  // the rest of the cursor line
+        .map(|x| x.trim_end())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    for keep in 1..lines.len() {
+        let tail = lines[keep..].iter().map(|x| x.trim_end()).filter(|x| !x.is_empty()).collect::<Vec<_>>();
+        if !tail.is_empty() && suffix_lines.starts_with(&tail) {
+            return lines[..keep].join("\n").trim_end().to_string();
+        }
+    }
+    completion.to_string()
+}
+
+// Places to cut the completion at: before line ends and after closing brackets, longest first
+fn _cut_candidates(completion: &str) -> Vec<usize> {
+    let mut candidates = completion.char_indices()
+        .filter_map(|(i, c)| match c {
+            '\n' => Some(i),
+            ')' | ']' | '}' => Some(i + 1),
+            _ => None,
+        })
+        .filter(|i| *i > 0 && *i < completion.len())
+        .collect::<Vec<_>>();
+    candidates.reverse();
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+// Splices the completion into the source at the cursor and re-parses it. If a shorter prefix of the
+// completion leaves fewer syntax errors in the file, the longest such prefix wins. Files without a
+// tree-sitter parser are left as is.
+pub fn postprocess_completion(
+    file_path: &PathBuf,
+    source: &str,
+    cursor_line: usize,
+    cursor_character: usize,
+    completion: &str,
+) -> String {
+    let text = Rope::from_str(source);
+    if cursor_line >= text.len_lines() {
+        return completion.to_string();
+    }
+    let line_start = text.line_to_char(cursor_line);
+    let line_len = text.line(cursor_line).len_chars();
+    let cursor = text.char_to_byte(line_start + cursor_character.min(line_len));
+    let (before, after) = source.split_at(cursor);
+
+    let completion = _drop_suffix_duplicates(completion, after);
+    if completion.trim().is_empty() {
+        return completion;
+    }
+    let mut language_parser = match get_parser_by_filename(file_path) {
+        Ok(x) => x,
+        Err(_) => return completion,
+    };
+    let parser = language_parser.get_parser();
+    let mut spliced_errors = |candidate: &str| {
+        _count_errors(parser, &format!("{}{}{}", before, candidate, after)).unwrap_or(usize::MAX)
+    };
+    let mut best_errors = spliced_errors(&completion);
+    let mut best = completion.as_str();
+    for cut in _cut_candidates(&completion) {
+        if best_errors == 0 {
+            break;
+        }
+        let candidate = completion[..cut].trim_end();
+        if candidate.is_empty() {
+            continue;
+        }
+        let errors = spliced_errors(candidate);
+        if errors < best_errors {
+            best_errors = errors;
+            best = candidate;
+        }
+    }
+    best.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionCandidate {
+    pub text: String,
+    pub finish_reason: String,
+    pub logprob: Option<f32>,  // mean over tokens
+}
+
+// Candidates that differ only in trailing whitespace are merged. If the endpoint gave log-probabilities for
+// every candidate, the most likely goes first, otherwise finished ones go before the ones cut by length.
+// Empty candidates go last either way, the order from the endpoint breaks ties.
+pub fn rank_candidates(mut candidates: Vec<CompletionCandidate>) -> Vec<CompletionCandidate> {
+    let all_logprobs = candidates.iter().all(|c| c.logprob.is_some());
+    candidates.sort_by(|a, b| {
+        let empty = a.text.trim().is_empty().cmp(&b.text.trim().is_empty());
+        empty.then_with(|| if all_logprobs {
+            b.logprob.partial_cmp(&a.logprob).unwrap_or(Ordering::Equal)
+        } else {
+            (a.finish_reason != "stop").cmp(&(b.finish_reason != "stop"))
+        })
+    });
+    let mut seen = HashSet::new();
+    candidates.retain(|c| seen.insert(c.text.trim_end().to_string()));
+    candidates
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_suffix_duplicates() {
+        let suffix = "\n    }\n}\n";
+        assert_eq!(_drop_suffix_duplicates("return x;\n    }\n}", suffix), "return x;");
+        assert_eq!(_drop_suffix_duplicates("return x;\n    }", suffix), "return x;");
+        // closes a block of its own, the indent doesn't match the line after the cursor
+        assert_eq!(_drop_suffix_duplicates("return x;\n}", suffix), "return x;\n}");
+        let nested_block = "if x {\n        y();\n    }";
+        assert_eq!(_drop_suffix_duplicates(nested_block, "\n}\n"), nested_block);
+        assert_eq!(_drop_suffix_duplicates("return x;\ny = 1;", suffix), "return x;\ny = 1;");
+        assert_eq!(_drop_suffix_duplicates("return x;", suffix), "return x;");
+    }
+
+    #[test]
+    fn test_trim_to_valid_prefix() {
+        let path = PathBuf::from("test.py");
+        let source = "def f(a, b):\n    return max(\n\nprint(f(1, 2))\n";
+        assert_eq!(postprocess_completion(&path, source, 1, 15, "a, b)"), "a, b)");
+        assert_eq!(postprocess_completion(&path, source, 1, 15, "a, b))]"), "a, b)");
+        // no parser for this file type
+        let path = PathBuf::from("test.unknown");
+        assert_eq!(postprocess_completion(&path, source, 1, 15, "a, b))]"), "a, b))]");
+    }
+
+    fn candidate(text: &str, finish_reason: &str, logprob: Option<f32>) -> CompletionCandidate {
+        CompletionCandidate { text: text.to_string(), finish_reason: finish_reason.to_string(), logprob }
+    }
+
+    #[test]
+    fn test_rank_candidates() {
+        let ranked = rank_candidates(vec![
+            candidate("", "stop", Some(-0.1)),
+            candidate("a + b", "stop", Some(-0.9)),
+            candidate("a - b", "stop", Some(-0.5)),
+            candidate("a + b\n", "length", Some(-0.2)),
+        ]);
+        assert_eq!(ranked.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(), vec!["a + b\n", "a - b", ""]);
+
+        // no logprobs from the endpoint
+        let ranked = rank_candidates(vec![
+            candidate("a + b", "length", None),
+            candidate("a - b", "stop", None),
+            candidate("a * b", "stop", None),
+            candidate("a - b", "stop", None),
+        ]);
+        assert_eq!(ranked.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(), vec!["a - b", "a * b", "a + b"]);
+    }
+}
@@ -14,27 +14,44 @@ use tree_sitter::Point;
 
 use crate::ast::ast_module::AstModule;
 use crate::ast::comments_wrapper::{get_language_id_by_filename, wrap_comments};
-use crate::call_validation::{CodeCompletionPost, SamplingParameters};
+use crate::ast::treesitter::language_id::LanguageId;
+use crate::call_validation::{ChatMessage, CodeCompletionPost, ContextFile, SamplingParameters};
 use crate::global_context::GlobalContext;
 use crate::completion_cache;
 use crate::files_in_workspace::DocumentInfo;
 use crate::scratchpad_abstract::HasTokenizerAndEot;
 use crate::scratchpad_abstract::ScratchpadAbstract;
+use crate::scratchpads::completion_neighbor_tabs::most_similar_chunks;
+use crate::scratchpads::completion_postprocess::{CompletionCandidate, postprocess_completion, rank_candidates};
+use crate::scratchpads::completion_token_budget::{FimBudgetRatios, FimTokenBudget};
 use crate::telemetry::snippets_collection;
 use crate::telemetry::telemetry_structs;
+use crate::vecdb::structs::VecdbSearch;
 
 
 const DEBUG: bool = false;
+const VECDB_QUERY_LINES_BEFORE: usize = 10;
+const VECDB_QUERY_LINES_AFTER: usize = 3;
+const VECDB_TOP_N: usize = 5;
+const NEIGHBOR_WINDOW_LINES_BEFORE: usize = 20;
+const NEIGHBOR_WINDOW_LINES_AFTER: usize = 5;
+const NEIGHBOR_MAX_FILES: usize = 20;
+const NEIGHBOR_TOP_N: usize = 5;
 
 
 pub struct SingleFileFIM {
     pub t: HasTokenizerAndEot,
     pub post: CodeCompletionPost,
-    pub order: String,
+    pub order: String,  // PSM, SPM, REPO (PSM with repo and file headers) or PREFIX (no suffix, no sentinels)
+    pub single_token_sentinels: bool,
     pub fim_prefix: String,
     pub fim_suffix: String,
     pub fim_middle: String,
+    pub fim_repo_name: String,
+    pub fim_file_sep: String,
     pub context_used: serde_json::Value,
+    pub prompt_budget: serde_json::Value,
+    pub budget_ratios: FimBudgetRatios,
     pub data4cache: completion_cache::CompletionSaveToCache,
     pub data4snippet: snippets_collection::SaveSnippet,
     pub ast_module: Arc<AMutex<Option<AstModule>>>,
@@ -53,9 +70,12 @@ impl SingleFileFIM {
     ) -> Self {
         let data4cache = completion_cache::CompletionSaveToCache::new(cache_arc, &post);
         let data4snippet = snippets_collection::SaveSnippet::new(tele_storage, &post);
-        SingleFileFIM { t: HasTokenizerAndEot::new(tokenizer), post, order, fim_prefix: String::new(),
-            fim_suffix: String::new(), fim_middle: String::new(),
+        SingleFileFIM { t: HasTokenizerAndEot::new(tokenizer), post, order, single_token_sentinels: true,
+            fim_prefix: String::new(), fim_suffix: String::new(), fim_middle: String::new(),
+            fim_repo_name: String::new(), fim_file_sep: String::new(),
             context_used: json!([]),
+            prompt_budget: json!({}),
+            budget_ratios: FimBudgetRatios::default(),
             data4cache,
             data4snippet,
             ast_module,
@@ -63,12 +83,90 @@ impl SingleFileFIM {
         }
     }
 
+    async fn vecdb_search(&self, text: &Rope, cursor_line: usize, file_path: &PathBuf) -> Result<ChatMessage, String> {
+        let line1 = cursor_line.saturating_sub(VECDB_QUERY_LINES_BEFORE);
+        let line2 = (cursor_line + VECDB_QUERY_LINES_AFTER + 1).min(text.len_lines());
+        let query = text.slice(text.line_to_char(line1)..text.line_to_char(line2)).to_string();
+        if query.trim().is_empty() {
+            return Err("nothing to search for around the cursor".to_string());
+        }
+        let vec_db = self.global_context.read().await.vec_db.clone();
+        let search_result = match *vec_db.lock().await {
+            Some(ref db) => db.search(query, VECDB_TOP_N).await?,
+            None => return Err("vecdb is not available".to_string()),
+        };
+        // the text around the cursor is in the prompt anyway
+        let mut results = search_result.results.into_iter()
+            .filter(|r| r.file_path != *file_path)
+            .collect::<Vec<_>>();
+        results.dedup_by(|a, b| a.file_path == b.file_path && a.window_text == b.window_text);
+        Ok(crate::at_commands::at_workspace::results2message(&results))
+    }
+
+    // Other sources in the request, then documents open in the IDE
+    async fn neighbor_files(&self) -> Vec<(String, String)> {
+        let cursor_file = &self.post.inputs.cursor.file;
+        let mut files: Vec<(String, String)> = self.post.inputs.sources.iter()
+            .filter(|(name, _)| *name != cursor_file)
+            .map(|(name, text)| (name.clone(), text.clone()))
+            .collect();
+        files.sort();
+        let (document_map, document_touched) = {
+            let gcx_locked = self.global_context.read().await;
+            (gcx_locked.documents_state.document_map.clone(), gcx_locked.documents_state.document_touched.lock().unwrap().clone())
+        };
+        let document_map_locked = document_map.read().await;
+        // LSP clients name files by url, HTTP clients by path
+        let mut open_docs = document_map_locked.iter()
+            .filter(|(url, _)| {
+                let url = url.to_string();
+                url != *cursor_file && !self.post.inputs.sources.contains_key(&url)
+            })
+            .map(|(url, doc)| (url.to_file_path().map(|p| p.display().to_string()).unwrap_or(url.to_string()), doc, document_touched.get(url)))
+            .filter(|(name, _, _)| name != cursor_file && !self.post.inputs.sources.contains_key(name))
+            .collect::<Vec<_>>();
+        // the user is working on the recently opened or changed ones, most likely those are related to the cursor file
+        open_docs.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        for (name, doc, _) in open_docs {
+            if files.len() >= NEIGHBOR_MAX_FILES {
+                break;
+            }
+            files.push((name, doc.text.to_string()));
+        }
+        files
+    }
+
+    async fn reload_context_files(&self, merged: &Vec<ContextFile>) -> Vec<ContextFile> {
+        // merging ranges breaks file_content, get the text again
+        let messages = crate::scratchpads::chat_utils_rag::reload_files(self.global_context.clone(), merged, false).await;
+        messages.into_iter()
+            .flat_map(|msg| serde_json::from_str::<Vec<ContextFile>>(&msg.content).unwrap_or_default())
+            .collect()
+    }
+
+    fn render_context_file(&self, cxfile: &ContextFile, lang: &LanguageId, workspace_folders: &Vec<PathBuf>) -> String {
+        if self.order == "REPO" {
+            let (_, path) = repo_and_path(workspace_folders, &PathBuf::from(&cxfile.file_name));
+            format!("{}{}\n{}", self.fim_file_sep, path, cxfile.file_content)
+        } else {
+            // comments, so the model doesn't take the context for a part of the file being edited
+            wrap_comments(&format!("Path: {}\n{}", cxfile.file_name, cxfile.file_content), lang) + "\n"
+        }
+    }
+
+    fn postprocessed_completion0(&mut self) -> String {
+        self.data4cache.completion0_text = postprocess(&self.post, &self.data4cache.completion0_text);
+        self.data4cache.completion0_text.clone()
+    }
+
     fn cleanup_prompt(&mut self, text: &String) -> String {
-        text.replace(&self.fim_prefix, "")
-            .replace(&self.fim_middle, "")
-            .replace(&self.fim_suffix, "")
-            .replace(&self.t.eos, "")
-            .replace(&self.t.eot, "")
+        let mut text = text.clone();
+        for sentinel in [&self.fim_prefix, &self.fim_middle, &self.fim_suffix, &self.fim_repo_name, &self.fim_file_sep, &self.t.eos, &self.t.eot] {
+            if !sentinel.trim().is_empty() {
+                text = text.replace(sentinel.as_str(), "");
+            }
+        }
+        text
     }
 }
 
@@ -83,14 +181,35 @@ impl ScratchpadAbstract for SingleFileFIM {
         self.fim_prefix = patch.get("fim_prefix").and_then(|x| x.as_str()).unwrap_or("<fim_prefix>").to_string();
         self.fim_suffix = patch.get("fim_suffix").and_then(|x| x.as_str()).unwrap_or("<fim_suffix>").to_string();
         self.fim_middle = patch.get("fim_middle").and_then(|x| x.as_str()).unwrap_or("<fim_middle>").to_string();
+        self.fim_repo_name = patch.get("repo_name").and_then(|x| x.as_str()).unwrap_or("<repo_name>").to_string();
+        self.fim_file_sep = patch.get("file_sep").and_then(|x| x.as_str()).unwrap_or("<file_sep>").to_string();
         self.t.eot = patch.get("eot").and_then(|x| x.as_str()).unwrap_or("<|endoftext|>").to_string();
         self.t.eos = patch.get("eos").and_then(|x| x.as_str()).unwrap_or("").to_string();
-        self.t.assert_one_token(&self.fim_prefix.as_str())?;
-        self.t.assert_one_token(&self.fim_suffix.as_str())?;
-        self.t.assert_one_token(&self.fim_middle.as_str())?;
-        self.t.assert_one_token(&self.t.eot.as_str())?;
+        self.budget_ratios = FimBudgetRatios::from_patch(patch)?;
+        if let Some(order) = patch.get("order").and_then(|x| x.as_str()) {
+            if order != "PSM" && order != "SPM" {
+                return Err(format!("order \"{}\" not recognized", order));
+            }
+            self.order = order.to_string();
+        }
+        let mut sentinels: Vec<&String> = match self.order.as_str() {
+            "PREFIX" => vec![],
+            "REPO" => vec![&self.fim_prefix, &self.fim_suffix, &self.fim_middle, &self.fim_repo_name, &self.fim_file_sep],
+            _ => vec![&self.fim_prefix, &self.fim_suffix, &self.fim_middle],
+        };
+        if self.single_token_sentinels && !sentinels.is_empty() && self.t.is_approximate() {
+            return Err("no tokenizer for this model, FIM sentinels can't be encoded; use --tokenizers-dir, or the FIM-SENTINELS scratchpad".to_string());
+        }
+        sentinels.push(&self.t.eot);
         if !self.t.eos.is_empty() {
-            self.t.assert_one_token(&self.t.eos.as_str())?;
+            sentinels.push(&self.t.eos);
+        }
+        for sentinel in sentinels {
+            if self.single_token_sentinels {
+                self.t.assert_one_token(sentinel.as_str())?;
+            } else if sentinel.trim().is_empty() {
+                return Err("sentinels can't be empty".to_string());
+            }
         }
         Ok(())
     }
@@ -100,7 +219,27 @@ impl ScratchpadAbstract for SingleFileFIM {
         context_size: usize,
         sampling_parameters_to_patch: &mut SamplingParameters,
     ) -> Result<String, String> {
-        let limit: i32 = context_size as i32 - self.post.parameters.max_new_tokens as i32;
+        let file_path = PathBuf::from(self.post.inputs.cursor.file.clone());
+        let workspace_folders = self.global_context.read().await.documents_state.workspace_folders.lock().unwrap().clone();
+        let (repo_header, file_header) = if self.order == "REPO" {
+            let (repo, path) = repo_and_path(&workspace_folders, &file_path);
+            (format!("{}{}", self.fim_repo_name, repo), format!("{}{}\n", self.fim_file_sep, path))
+        } else {
+            ("".to_string(), "".to_string())
+        };
+        let sentinels = match self.order.as_str() {
+            "PREFIX" => "".to_string(),
+            _ => format!("{}{}{}", self.fim_prefix, self.fim_suffix, self.fim_middle),
+        };
+        let special_tokens = self.t.count_tokens(format!("{}{}{}{}", self.t.eos, repo_header, file_header, sentinels).as_str())? as usize;
+        let neighbor_files = self.neighbor_files().await;
+        let mut budget = FimTokenBudget::new(
+            context_size,
+            self.post.parameters.max_new_tokens,
+            special_tokens,
+            &self.budget_ratios,
+            self.post.use_ast || self.post.use_vecdb || !neighbor_files.is_empty(),
+        )?;
         let supports_stop = true; // some hf models do not support stop, but it's a thing of the past?
         if supports_stop {
             let mut stop_list = vec![self.t.eot.clone(), "\n\n".to_string()];
@@ -117,12 +256,9 @@ impl ScratchpadAbstract for SingleFileFIM {
         let text = Rope::from_str(&*source);
 
         let pos = &self.post.inputs.cursor;
-        let file_path = PathBuf::from(self.post.inputs.cursor.file.clone());
         let mut before_iter = text.lines_at(pos.line as usize).reversed();
         let mut after_iter = text.lines_at(pos.line as usize + 1);
-        let mut extra_context = String::new();
-        let mut tokens_used = 0;
-        let ast_messages: Vec<crate::call_validation::ChatMessage> = if self.post.use_ast {
+        let mut context_messages: Vec<ChatMessage> = if self.post.use_ast {
             let chat_message_maybe = match *self.ast_module.lock().await {
                 Some(ref mut ast) => {
                     let doc_info = match DocumentInfo::from_pathbuf(&file_path) {
@@ -148,14 +284,39 @@ impl ScratchpadAbstract for SingleFileFIM {
         } else {
             vec![]
         };
-        let context_ctx_this_message = 1024;  // FIXME: calculate better, subtract from limit
+        if self.post.use_vecdb {
+            match self.vecdb_search(&text, pos.line as usize, &file_path).await {
+                Ok(context_message) => context_messages.push(context_message),
+                Err(err) => error!("can't fetch vecdb results: {}", err),
+            }
+        }
         let postprocessed_messages = crate::scratchpads::chat_utils_rag::postprocess_at_results(
             self.global_context.clone(),
-            ast_messages,
+            context_messages,
             self.t.tokenizer.clone(),
-            context_ctx_this_message,
+            budget.context_limit,
         ).await;
-        self.context_used = json!(postprocessed_messages);
+        let mut context_files = self.reload_context_files(&postprocessed_messages).await;
+        if !neighbor_files.is_empty() {
+            let line = pos.line as usize;
+            let line1 = line.saturating_sub(NEIGHBOR_WINDOW_LINES_BEFORE);
+            let line2 = (line + NEIGHBOR_WINDOW_LINES_AFTER + 1).min(text.len_lines());
+            let cursor_window = text.slice(text.line_to_char(line1)..text.line_to_char(line2)).to_string();
+            context_files.extend(most_similar_chunks(&cursor_window, &neighbor_files, NEIGHBOR_TOP_N));
+        }
+        let lang = get_language_id_by_filename(&file_path).unwrap_or_default();
+        let mut extra_context = String::new();
+        let mut files_used = vec![];
+        for cxfile in context_files {
+            let wrapped = self.render_context_file(&cxfile, &lang, &workspace_folders);
+            let tokens = self.t.count_tokens(wrapped.as_str())? as usize;
+            if budget.context_tokens + tokens > budget.context_limit {
+                continue;
+            }
+            budget.context_tokens += tokens;
+            extra_context.push_str(&wrapped);
+            files_used.push(cxfile);
+        }
 
         let mut before_line = before_iter.next();
 
@@ -167,7 +328,7 @@ impl ScratchpadAbstract for SingleFileFIM {
         let mut after_line = after_iter.next();
 
         let cursor_line2: String;
-        if self.post.inputs.multiline {
+        if self.post.inputs.multiline && self.order != "PREFIX" {
             cursor_line2 = text.line(pos.line as usize).slice(col..).to_string();
         } else {
             cursor_line2 = "".to_string();
@@ -175,66 +336,124 @@ impl ScratchpadAbstract for SingleFileFIM {
 
         let mut before = vec![];
         let mut after = String::new();
-        tokens_used += self.t.count_tokens(
+        budget.cursor_tokens = self.t.count_tokens(
             (cursor_line1.clone() + &cursor_line2).as_str()
-        )?;
-        while before_line.is_some() || after_line.is_some() {
-            if let Some(before_line) = before_line {
-                let before_line = before_line.to_string();
-                let tokens = self.t.count_tokens(before_line.as_str())?;
-                if tokens_used + tokens > limit {
-                    break;
-                }
-                tokens_used += tokens;
-                before.push(before_line);
+        )? as usize;
+        if budget.cursor_tokens > budget.file_text_left() {
+            return Err(format!("cursor line takes {} tokens, more than the prompt limit {}", budget.cursor_tokens, budget.prompt_limit));
+        }
+        // suffix up to its share first, then prefix takes everything left, and if the prefix
+        // runs out of lines before the budget does, the suffix continues
+        let suffix_limit = if self.order == "PREFIX" { 0 } else { budget.suffix_limit(&self.budget_ratios) };
+        let mut suffix_full = false;
+        while let Some(line) = after_line {
+            let line = line.to_string();
+            let tokens = self.t.count_tokens(line.as_str())? as usize;
+            if budget.suffix_tokens + tokens > suffix_limit {
+                suffix_full = true;
+                break;
             }
-            if let Some(after_line) = after_line {
-                let after_line = after_line.to_string();
-                let tokens = self.t.count_tokens(after_line.as_str())?;
-                if tokens_used + tokens > limit {
+            budget.suffix_tokens += tokens;
+            after.push_str(&line);
+            after_line = after_iter.next();
+        }
+        while let Some(line) = before_line {
+            let line = line.to_string();
+            let tokens = self.t.count_tokens(line.as_str())? as usize;
+            if tokens > budget.file_text_left() {
+                break;
+            }
+            budget.prefix_tokens += tokens;
+            before.push(line);
+            before_line = before_iter.next();
+        }
+        if before_line.is_none() && suffix_full && self.order != "PREFIX" {
+            while let Some(line) = after_line {
+                let line = line.to_string();
+                let tokens = self.t.count_tokens(line.as_str())? as usize;
+                if tokens > budget.file_text_left() {
                     break;
                 }
-                tokens_used += tokens;
-                after.push_str(&after_line);
+                budget.suffix_tokens += tokens;
+                after.push_str(&line);
+                after_line = after_iter.next();
             }
-            before_line = before_iter.next();
-            after_line = after_iter.next();
         }
-        info!("single file FIM prompt {} tokens used < limit {}", tokens_used, limit);
-        let prompt: String;
-        if self.order == "PSM" {
-            prompt = format!(
-                "{}{}{}{}{}{}{}{}{}",
-                self.t.eos,
-                self.fim_prefix,
-                extra_context,
-                before.into_iter().rev().collect::<Vec<_>>().join(""),
-                cursor_line1,
-                self.fim_suffix,
-                cursor_line2,
-                after,
-                self.fim_middle
-            );
-        } else if self.order == "SPM" {
-            prompt = format!(
-                "{}{}{}{}{}{}{}{}{}",
-                self.t.eos,
-                self.fim_suffix,
-                extra_context,
-                cursor_line2,
-                after,
-                self.fim_prefix,
-                before.into_iter().rev().collect::<Vec<_>>().join(""),
-                cursor_line1,
-                self.fim_middle,
-            );
-        } else {
-            return Err(format!("order \"{}\" not recognized", self.order));
+        // before: prefix lines, the nearest to the cursor first
+        let assemble_prompt = |before: &Vec<String>| -> Result<String, String> {
+            let before = before.iter().rev().cloned().collect::<Vec<_>>().join("");
+            let prompt = if self.order == "PSM" {
+                format!(
+                    "{}{}{}{}{}{}{}{}{}",
+                    self.t.eos,
+                    self.fim_prefix,
+                    extra_context,
+                    before,
+                    cursor_line1,
+                    self.fim_suffix,
+                    cursor_line2,
+                    after,
+                    self.fim_middle
+                )
+            } else if self.order == "REPO" {
+                format!(
+                    "{}{}{}{}{}{}{}{}{}{}{}",
+                    self.t.eos,
+                    repo_header,
+                    extra_context,
+                    file_header,
+                    self.fim_prefix,
+                    before,
+                    cursor_line1,
+                    self.fim_suffix,
+                    cursor_line2,
+                    after,
+                    self.fim_middle
+                )
+            } else if self.order == "PREFIX" {
+                format!(
+                    "{}{}{}{}",
+                    self.t.eos,
+                    extra_context,
+                    before,
+                    cursor_line1,
+                )
+            } else if self.order == "SPM" {
+                format!(
+                    "{}{}{}{}{}{}{}{}{}",
+                    self.t.eos,
+                    self.fim_suffix,
+                    extra_context,
+                    cursor_line2,
+                    after,
+                    self.fim_prefix,
+                    before,
+                    cursor_line1,
+                    self.fim_middle,
+                )
+            } else {
+                return Err(format!("order \"{}\" not recognized", self.order));
+            };
+            Ok(prompt)
+        };
+        let mut prompt = assemble_prompt(&before)?;
+        budget.prompt_tokens = self.t.count_tokens(prompt.as_str())? as usize;
+        // tokens merge across line boundaries, the whole prompt can come out a bit longer than the sum of its lines
+        while budget.prompt_tokens + budget.max_new_tokens > budget.n_ctx {
+            let Some(line) = before.pop() else {
+                return Err(format!("FIM prompt {} tokens + max_new_tokens {} > n_ctx {}", budget.prompt_tokens, budget.max_new_tokens, budget.n_ctx));
+            };
+            budget.prefix_tokens = budget.prefix_tokens.saturating_sub(self.t.count_tokens(line.as_str())? as usize);
+            prompt = assemble_prompt(&before)?;
+            budget.prompt_tokens = self.t.count_tokens(prompt.as_str())? as usize;
         }
+        info!("single file FIM prompt {} tokens (context {}, prefix {}, suffix {}, cursor {}), limit {}",
+            budget.prompt_tokens, budget.context_tokens, budget.prefix_tokens, budget.suffix_tokens, budget.cursor_tokens, budget.prompt_limit + budget.special_tokens);
+        self.context_used = json!(files_used);
+        self.prompt_budget = json!(budget);
         if DEBUG {
             info!("cursor position\n{:?}", self.post.inputs.cursor);
             info!("prompt\n{}", prompt);
-            info!("re-encode whole prompt again gives {} tokens", self.t.count_tokens(prompt.as_str())?);
         }
         Ok(prompt)
     }
@@ -242,9 +461,10 @@ impl ScratchpadAbstract for SingleFileFIM {
     fn response_n_choices(
         &mut self,
         choices: Vec<String>,
-        stopped: Vec<bool>
+        stopped: Vec<bool>,
+        logprobs: Vec<Option<f32>>,
     ) -> Result<serde_json::Value, String> {
-        let json_choices = choices.iter().enumerate().map(|(i, x)| {
+        let candidates = choices.iter().enumerate().map(|(i, x)| {
             let (mut cc, mut finished) = cut_result(&x, self.t.eot.as_str(), self.post.inputs.multiline);
             finished |= stopped[i];
             let finish_reason = if finished {
@@ -253,27 +473,43 @@ impl ScratchpadAbstract for SingleFileFIM {
             } else {
                 "length"
             }.to_string();
-            if i==0 {
-                self.data4cache.completion0_text = cc.clone();
-                self.data4cache.completion0_finish_reason = finish_reason.clone();
+            CompletionCandidate {
+                text: postprocess(&self.post, &cc),
+                finish_reason,
+                logprob: logprobs.get(i).cloned().flatten(),
             }
+        }).collect::<Vec<_>>();
+        let ranked = rank_candidates(candidates);
+        if let Some(best) = ranked.first() {
+            self.data4cache.completion0_text = best.text.clone();
+            self.data4cache.completion0_finish_reason = best.finish_reason.clone();
+        }
+        self.data4cache.other_completions = ranked.iter().skip(1)
+            .map(|c| (c.text.clone(), c.finish_reason.clone()))
+            .collect();
+        snippets_collection::snippet_register_from_data4cache(&self.data4snippet, &mut self.data4cache);
+        let snippet_telemetry_ids = std::iter::once(self.data4cache.completion0_snippet_telemetry_id)
+            .chain(self.data4cache.other_snippet_telemetry_ids.iter().map(|x| Some(*x)))
+            .collect::<Vec<_>>();
+        let json_choices = ranked.iter().enumerate().map(|(i, c)| {
             serde_json::json!({
                 "index": i,
-                "code_completion": cc,
-                "finish_reason": finish_reason.clone(),
+                "code_completion": c.text,
+                "finish_reason": c.finish_reason,
+                "snippet_telemetry_id": snippet_telemetry_ids.get(i).cloned().flatten(),
             })
         }).collect::<Vec<_>>();
         if DEBUG {
             info!("response_n_choices\n{:?}", json_choices);
         }
 
-        snippets_collection::snippet_register_from_data4cache(&self.data4snippet, &mut self.data4cache);
         return Ok(serde_json::json!(
             {
                 "choices": json_choices,
                 "snippet_telemetry_id": self.data4cache.completion0_snippet_telemetry_id,
                 "model": self.post.model.clone(),
                 "context": self.context_used,
+                "prompt_budget": self.prompt_budget,
             }
         ));
     }
@@ -286,6 +522,8 @@ impl ScratchpadAbstract for SingleFileFIM {
     ) -> Result<(serde_json::Value, bool), String> {
         let mut finished;
         let json_choices;
+        // post-processing needs the whole completion, it can only go out in one piece at the end
+        let hold_back = !self.post.no_postprocess;
         // info!("XXXXX delta: {:?}", delta);
         // info!("XXXXX stop_toks: {:?}", stop_toks);
         // info!("XXXXX stop_length: {:?}", stop_length);
@@ -299,6 +537,9 @@ impl ScratchpadAbstract for SingleFileFIM {
                 self.data4cache.completion0_finish_reason = if finished { "stop".to_string() } else { "".to_string() };
             }
             self.data4cache.completion0_text.push_str(&s);
+            if hold_back {
+                s = if finished { self.postprocessed_completion0() } else { "".to_string() };
+            }
             json_choices = serde_json::json!([{
                 "index": 0,
                 "code_completion": s,
@@ -306,9 +547,10 @@ impl ScratchpadAbstract for SingleFileFIM {
             }]);
         } else {
             assert!(stop_length);
+            let s = if hold_back { self.postprocessed_completion0() } else { "".to_string() };
             json_choices = serde_json::json!([{
                 "index": 0,
-                "code_completion": "",
+                "code_completion": s,
                 "finish_reason": "length"
             }]);
             self.data4cache.completion0_finish_reason = "length".to_string();
@@ -419,6 +661,34 @@ impl ScratchpadAbstract for SingleFileFIM {
 //     (extra_context.join(""), tokens_used)
 // }
 
+fn postprocess(post: &CodeCompletionPost, completion: &str) -> String {
+    if post.no_postprocess {
+        return completion.to_string();
+    }
+    match post.inputs.sources.get(&post.inputs.cursor.file) {
+        Some(source) => postprocess_completion(
+            &PathBuf::from(&post.inputs.cursor.file),
+            source,
+            post.inputs.cursor.line as usize,
+            post.inputs.cursor.character as usize,
+            completion,
+        ),
+        None => completion.to_string(),
+    }
+}
+
+// Repo name and path relative to the workspace folder the file is in, file name only if it's not in any
+fn repo_and_path(workspace_folders: &Vec<PathBuf>, file_path: &PathBuf) -> (String, String) {
+    for folder in workspace_folders {
+        if let Ok(relative) = file_path.strip_prefix(folder) {
+            let repo = folder.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            return (repo, relative.display().to_string());
+        }
+    }
+    let file_name = file_path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    ("".to_string(), file_name)
+}
+
 fn cut_result(text: &str, eot_token: &str, multiline: bool) -> (String, bool) {
     let mut cut_at = vec![];
     if let Some(x) = text.find(eot_token) {
@@ -0,0 +1,120 @@
+use serde::Serialize;
+
+const DEFAULT_CONTEXT_RATIO: f64 = 0.25;
+const DEFAULT_SUFFIX_RATIO: f64 = 0.5;
+
+
+// Shares of the prompt, configurable per model in the scratchpad patch, for example
+// "FIM-PSM": {"context_ratio": 0.3, "suffix_ratio": 0.25}
+#[derive(Debug, Clone, PartialEq)]
+pub struct FimBudgetRatios {
+    pub context: f64,  // of the prompt, for AST and vecdb context
+    pub suffix: f64,   // of what's left for the file text, prefix gets the rest
+}
+
+impl Default for FimBudgetRatios {
+    fn default() -> Self {
+        FimBudgetRatios { context: DEFAULT_CONTEXT_RATIO, suffix: DEFAULT_SUFFIX_RATIO }
+    }
+}
+
+impl FimBudgetRatios {
+    pub fn from_patch(patch: &serde_json::Value) -> Result<FimBudgetRatios, String> {
+        let mut ratios = FimBudgetRatios::default();
+        for (key, field) in [("context_ratio", &mut ratios.context), ("suffix_ratio", &mut ratios.suffix)] {
+            if let Some(value) = patch.get(key) {
+                let value = value.as_f64().ok_or(format!("{} should be a number", key))?;
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(format!("{} should be between 0 and 1, got {}", key, value));
+                }
+                *field = value;
+            }
+        }
+        Ok(ratios)
+    }
+}
+
+// Token accounting for one FIM prompt, it goes to the response as is. Limits are computed
+// upfront, the rest is filled in while the prompt is built.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct FimTokenBudget {
+    pub n_ctx: usize,
+    pub max_new_tokens: usize,
+    pub special_tokens: usize,
+    pub prompt_limit: usize,
+    pub context_limit: usize,
+    pub context_tokens: usize,
+    pub cursor_tokens: usize,
+    pub prefix_tokens: usize,
+    pub suffix_tokens: usize,
+    pub prompt_tokens: usize,  // the whole prompt encoded again, can differ a bit from the sum of the parts
+}
+
+impl FimTokenBudget {
+    pub fn new(
+        n_ctx: usize,
+        max_new_tokens: usize,
+        special_tokens: usize,
+        ratios: &FimBudgetRatios,
+        use_context: bool,
+    ) -> Result<FimTokenBudget, String> {
+        let prompt_limit = n_ctx.checked_sub(max_new_tokens + special_tokens)
+            .filter(|x| *x > 0)
+            .ok_or(format!("n_ctx {} is too small for max_new_tokens {}", n_ctx, max_new_tokens))?;
+        let context_limit = if use_context { (prompt_limit as f64 * ratios.context) as usize } else { 0 };
+        Ok(FimTokenBudget {
+            n_ctx,
+            max_new_tokens,
+            special_tokens,
+            prompt_limit,
+            context_limit,
+            ..Default::default()
+        })
+    }
+
+    // Context that didn't use all of its share leaves the rest to the file text
+    pub fn file_text_left(&self) -> usize {
+        self.prompt_limit.saturating_sub(self.context_tokens + self.cursor_tokens + self.prefix_tokens + self.suffix_tokens)
+    }
+
+    pub fn suffix_limit(&self, ratios: &FimBudgetRatios) -> usize {
+        let file_text = self.prompt_limit.saturating_sub(self.context_tokens + self.cursor_tokens);
+        (file_text as f64 * ratios.suffix) as usize
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_ratios_from_patch() {
+        assert_eq!(FimBudgetRatios::from_patch(&json!({})).unwrap(), FimBudgetRatios::default());
+        assert_eq!(
+            FimBudgetRatios::from_patch(&json!({"fim_prefix": "<PRE>", "context_ratio": 0.1})).unwrap(),
+            FimBudgetRatios { context: 0.1, suffix: DEFAULT_SUFFIX_RATIO },
+        );
+        assert!(FimBudgetRatios::from_patch(&json!({"suffix_ratio": 1.5})).is_err());
+        assert!(FimBudgetRatios::from_patch(&json!({"suffix_ratio": "half"})).is_err());
+    }
+
+    #[test]
+    fn test_budget() {
+        let ratios = FimBudgetRatios { context: 0.25, suffix: 0.5 };
+        let mut budget = FimTokenBudget::new(2048, 50, 3, &ratios, true).unwrap();
+        assert_eq!(budget.prompt_limit, 1995);
+        assert_eq!(budget.context_limit, 498);
+        budget.context_tokens = 95;
+        budget.cursor_tokens = 100;
+        assert_eq!(budget.suffix_limit(&ratios), 900);
+        budget.suffix_tokens = 300;
+        assert_eq!(budget.file_text_left(), 1500);
+
+        let budget = FimTokenBudget::new(2048, 50, 3, &ratios, false).unwrap();
+        assert_eq!(budget.context_limit, 0);
+        assert!(FimTokenBudget::new(64, 64, 3, &ratios, false).is_err());
+    }
+}
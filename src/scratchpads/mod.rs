@@ -6,6 +6,9 @@ use tokenizers::Tokenizer;
 use crate::ast::ast_module::AstModule;
 
 pub mod completion_single_file_fim;
+pub mod completion_neighbor_tabs;
+pub mod completion_postprocess;
+pub mod completion_token_budget;
 pub mod chat_generic;
 pub mod chat_llama2;
 pub mod chat_passthrough;
@@ -43,6 +46,14 @@ pub async fn create_code_completion_scratchpad(
         result = Box::new(completion_single_file_fim::SingleFileFIM::new(tokenizer_arc, post, "PSM".to_string(), cache_arc, tele_storage, ast_module, global_context.clone()));
     } else if scratchpad_name == "FIM-SPM" {
         result = Box::new(completion_single_file_fim::SingleFileFIM::new(tokenizer_arc, post, "SPM".to_string(), cache_arc, tele_storage, ast_module, global_context.clone()));
+    } else if scratchpad_name == "FIM-SENTINELS" {
+        let mut fim = completion_single_file_fim::SingleFileFIM::new(tokenizer_arc, post, "PSM".to_string(), cache_arc, tele_storage, ast_module, global_context.clone());
+        fim.single_token_sentinels = false;
+        result = Box::new(fim);
+    } else if scratchpad_name == "FIM-REPO" {
+        result = Box::new(completion_single_file_fim::SingleFileFIM::new(tokenizer_arc, post, "REPO".to_string(), cache_arc, tele_storage, ast_module, global_context.clone()));
+    } else if scratchpad_name == "PREFIX-ONLY" {
+        result = Box::new(completion_single_file_fim::SingleFileFIM::new(tokenizer_arc, post, "PREFIX".to_string(), cache_arc, tele_storage, ast_module, global_context.clone()));
     } else {
         return Err(format!("This rust binary doesn't have code completion scratchpad \"{}\" compiled in", scratchpad_name));
     }
@@ -73,6 +73,24 @@ pub fn snippet_register_from_data4cache(
         return;
     }
     data4cache.completion0_snippet_telemetry_id = Some(snippet_register(&ss, data4cache.completion0_text.clone()));
+    // the IDE can show any of the candidates, accepting one of them should count that one
+    data4cache.other_snippet_telemetry_ids = data4cache.other_completions.iter()
+        .map(|(text, _)| snippet_register(&ss, text.clone()))
+        .collect();
+}
+
+pub fn snippet_register_from_disk_cache(
+    ss: &SaveSnippet,
+    cached_json_value: &mut serde_json::Value,
+) {
+    // Snippet ids are not stored on disk, they start over after a restart
+    if let Some(choices) = cached_json_value.get_mut("choices").and_then(|x| x.as_array_mut()) {
+        for choice in choices.iter_mut() {
+            let grey_text = choice["code_completion"].as_str().unwrap_or("").to_string();
+            choice["snippet_telemetry_id"] = serde_json::json!(snippet_register(&ss, grey_text));
+        }
+    }
+    cached_json_value["snippet_telemetry_id"] = cached_json_value["choices"][0]["snippet_telemetry_id"].clone();
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]